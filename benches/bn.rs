@@ -1,13 +1,16 @@
 use criterion::{Criterion, black_box, criterion_group, criterion_main};
 use mateus::benchmark::BRATKO_KOPEC;
 use mateus::mgen::*;
+use mateus::pawns::PawnTable;
+use mateus::transposition::Transpositions;
 use mateus::val::Colour;
+use mateus::{Game, SearchConstraints};
 
 fn bench_move_gen(c: &mut Criterion) {
     let fen = BRATKO_KOPEC[0].0;
     let board = Board::from_fen(fen).expect("Faild to load fen");
     c.bench_function("mgen BK1", |b| {
-        b.iter(|| black_box(board.moves(false, false)))
+        b.iter(|| black_box(black_box(&board).moves(false, false)))
     });
 }
 
@@ -15,29 +18,87 @@ fn bench_in_check(c: &mut Criterion) {
     let fen = BRATKO_KOPEC[0].0;
     let board = Board::from_fen(fen).expect("Faild to load fen");
     c.bench_function("in_check BK1", |b| {
-        b.iter(|| black_box(board.in_check(Colour::white())))
+        b.iter(|| black_box(black_box(&board).in_check(Colour::white())))
     });
 }
 
 fn bench_mobility(c: &mut Criterion) {
     let fen = BRATKO_KOPEC[0].0;
     let board = Board::from_fen(fen).expect("Faild to load fen");
-    c.bench_function("mobility BK1", |b| b.iter(|| black_box(board.mobility())));
+    c.bench_function("mobility BK1", |b| {
+        b.iter(|| black_box(black_box(&board).mobility()))
+    });
+}
+
+fn bench_perft(c: &mut Criterion) {
+    let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    let mut board = Board::from_fen(fen).expect("Faild to load fen");
+    c.bench_function("perft Kiwipete depth 3", |b| {
+        b.iter(|| black_box(board.perft(3)))
+    });
 }
 
 fn bench_pawn_structure(c: &mut Criterion) {
     let fen = BRATKO_KOPEC[0].0;
     let board = Board::from_fen(fen).expect("Faild to load fen");
     c.bench_function("pawn_structure BK1", |b| {
-        b.iter(|| black_box(board.score_pawn_structure()))
+        b.iter(|| black_box(black_box(&board).score_pawn_structure()))
     });
 }
 
+fn bench_pawn_table(c: &mut Criterion) {
+    let fen = BRATKO_KOPEC[0].0;
+    let board = Board::from_fen(fen).expect("Faild to load fen");
+    let mut table = PawnTable::default();
+    c.bench_function("pawn_table BK1", |b| {
+        b.iter(|| black_box(table.get(black_box(&board))))
+    });
+}
+
+// Fixed depth search of the Bratko-Kopec suite with a small (1 MB) transposition
+// table, so that the replacement policy matters. Compares a direct mapped table
+// (1 entry per bucket, always replace) with the default buckets of the same size -
+// prints the total node counts at depth 5 and 6, and times the depth 5 searches.
+fn bench_search(c: &mut Criterion) {
+    fn search(bucket_size: usize, depth: u8) -> usize {
+        let sc = SearchConstraints::default().depth(depth);
+        let mut game = Game::with_hash(Board::default(), 1);
+        game.ttable = Transpositions::with_bucket_size(1, bucket_size);
+        let mut nodes = 0;
+        for (fen, _) in BRATKO_KOPEC {
+            game.set_position(Board::from_fen(fen).expect("Faild to load fen"));
+            game.ttable.clear();
+            let moves = game.board.legal_moves();
+            nodes += game.score_moves(&moves, &sc, false).1.nodes;
+        }
+        nodes
+    }
+    for depth in [5, 6] {
+        let (direct, buckets) = (search(1, depth), search(4, depth));
+        let change = 100.0 * (buckets as f64 / direct as f64 - 1.0);
+        println!(
+            "search BK depth {depth}: {direct} nodes direct mapped, {buckets} nodes with buckets ({change:+.1}%)"
+        );
+    }
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+    for bucket_size in [1, 4] {
+        group.bench_function(
+            format!("search BK depth 5, bucket size {bucket_size}"),
+            |b| b.iter(|| black_box(search(bucket_size, 5))),
+        );
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_move_gen,
     bench_in_check,
     bench_mobility,
-    bench_pawn_structure
+    bench_perft,
+    bench_pawn_structure,
+    bench_pawn_table,
+    bench_search
 );
 criterion_main!(benches);
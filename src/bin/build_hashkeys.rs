@@ -1,11 +1,12 @@
 // generate hashkeys_generated.rs
 
 fn random_array_u64(label: &str) {
-    let mut a: [u64; 64] = [0u64; 64];
-    for x in &mut a {
-        *x = rand::random::<u64>();
-    }
-    println!("pub const {label}: [u64;64] = {:?};", a);
+    random_array_n(label, 64);
+}
+
+fn random_array_n(label: &str, n: usize) {
+    let a: Vec<u64> = (0..n).map(|_| rand::random::<u64>()).collect();
+    println!("pub const {label}: [u64;{n}] = {:?};", a);
 }
 
 fn main() {
@@ -25,4 +26,8 @@ fn main() {
     random_array_u64("NIL_HASH");
     println!("pub const NIL_HASH: [u64;64] = [0;64];");
     println!("pub const WHITE_HASH: u64={};", rand::random::<u64>());
+    // castling rights: white short, long, black short, long
+    random_array_n("CASTLE_HASH", 4);
+    // en passant file: h..a
+    random_array_n("EN_PASSANT_HASH", 8);
 }
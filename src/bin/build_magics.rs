@@ -0,0 +1,55 @@
+// generate magics_generated.rs - magic numbers for the sliding piece attack tables
+
+use mateus::bitmaps::{BISHOP_DIRECTIONS, Magic, ROOK_DIRECTIONS, slider_attacks, slider_mask};
+
+/// trial and error - a magic maps all blocker subsets of the mask to
+/// table entries without destructive collisions
+fn find_magic(frm: usize, directions: &[(isize, isize)]) -> u64 {
+    let mask = slider_mask(frm, directions);
+    let mut subsets = Vec::new();
+    let mut occupied = 0u64;
+    loop {
+        subsets.push((occupied, slider_attacks(frm, occupied, directions)));
+        occupied = occupied.wrapping_sub(mask) & mask;
+        if occupied == 0 {
+            break;
+        }
+    }
+
+    let mut table = vec![(0, 0u64); subsets.len()]; // (trial, attacks)
+    for trial in 1.. {
+        // sparse random numbers make good magics
+        let magic = rand::random::<u64>() & rand::random::<u64>() & rand::random::<u64>();
+        if (mask.wrapping_mul(magic) >> 56).count_ones() < 6 {
+            continue;
+        }
+        let m = Magic {
+            mask,
+            magic,
+            shift: 64 - mask.count_ones(),
+            offset: 0,
+        };
+        let ok = subsets.iter().all(|&(occupied, attacks)| {
+            let e = &mut table[m.index(occupied)];
+            if e.0 != trial {
+                *e = (trial, attacks);
+            }
+            e.1 == attacks
+        });
+        if ok {
+            return magic;
+        }
+    }
+    unreachable!()
+}
+
+fn magics(label: &str, directions: &[(isize, isize)]) {
+    let a: Vec<u64> = (0..64).map(|frm| find_magic(frm, directions)).collect();
+    println!("pub const {label}: [u64;64] = {:?};", a);
+}
+
+fn main() {
+    println!("// Magic numbers generated by bin/build_magics.rs\n");
+    magics("ROOK_MAGICS", &ROOK_DIRECTIONS);
+    magics("BISHOP_MAGICS", &BISHOP_DIRECTIONS);
+}
@@ -0,0 +1,27 @@
+// generate polyglot_keys_generated.rs from the published Random64 table, e.g. the C
+// listing in http://hgm.nubati.net/book_format.html
+//    cargo run --bin build_polyglot_keys -- random64.c > src/polyglot_keys_generated.rs
+
+use mateus::polyglot::{Keys, N_KEYS};
+
+fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("Usage: build_polyglot_keys <Random64 listing>");
+        std::process::exit(1);
+    };
+    match Keys::load(&path) {
+        Ok(keys) => {
+            println!("// Polyglot Random64 keys generated by bin/build_polyglot_keys.rs\n");
+            // hex - as in the published listing
+            println!("pub const RANDOM64: [u64; {N_KEYS}] = [");
+            for k in keys.values() {
+                println!("    {k:#018x},");
+            }
+            println!("];");
+        }
+        Err(m) => {
+            eprintln!("{m}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -8,6 +8,9 @@ use clap::Parser;
 use csv::Writer;
 use flate2::read::GzDecoder;
 use mateus::Game;
+use mateus::mgen::Move;
+use mateus::openings::BookStats;
+use mateus::polyglot::Keys;
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs::File;
@@ -21,6 +24,18 @@ struct Args {
     #[arg(short, long = "elo", default_value_t = 2000)]
     ///minimum elo for both players
     e: usize,
+    #[arg(long)]
+    ///build an opening book instead of csv files - text, or Polyglot if the file ends in .bin
+    book: Option<String>,
+    #[arg(long, default_value_t = 16)]
+    ///max ply counted in the opening book
+    book_depth: usize,
+    #[arg(long, default_value_t = 3)]
+    ///min number of games a move is played in to enter the book
+    book_min_count: u32,
+    #[arg(long)]
+    ///Polyglot Random64 keys (text file with the 781 0x.. numbers) - replaces the built-in keys for .bin books
+    book_keys: Option<String>,
     /// Input files
     #[arg(required = true)]
     files: Vec<String>,
@@ -46,7 +61,7 @@ struct FicsG {
     eco: String,
     ply_count: usize,
     result: &'static str,
-    moves: Vec<(u8, u8)>,
+    moves: Vec<Move>,
     fens: Vec<Vec<u8>>,
     comment: &'static str,
 }
@@ -157,7 +172,10 @@ fn static_comment(s: &str) -> &'static str {
     }
 }
 
-fn parse_moves(fg: &mut FicsG, line: String) -> (Vec<(u8, u8)>, Vec<Vec<u8>>, &'static str) {
+// moves, csv encoded positions, outcome
+type ParsedMoves = (Vec<Move>, Vec<Vec<u8>>, &'static str);
+
+fn parse_moves(fg: &mut FicsG, line: String) -> ParsedMoves {
     let result = "000"; // dummy
     let mut lmoves = Vec::new();
     let mut lfens = Vec::new();
@@ -201,7 +219,7 @@ fn parse_moves(fg: &mut FicsG, line: String) -> (Vec<(u8, u8)>, Vec<Vec<u8>>, &'
         match alg_labels.iter().position(|r| r == s) {
             Some(index) => {
                 game.make_move(moves[index]);
-                lmoves.push((moves[index].frm(), moves[index].to()));
+                lmoves.push(moves[index]);
                 lfens.push(game.board.to_csv());
             }
             None => {
@@ -216,7 +234,7 @@ fn parse_moves(fg: &mut FicsG, line: String) -> (Vec<(u8, u8)>, Vec<Vec<u8>>, &'
     (lmoves, lfens, result)
 }
 
-fn read_games(fname: &str, min_elo: usize) -> io::Result<Vec<FicsG>> {
+fn read_games(fname: &str, min_elo: usize, draws: bool) -> io::Result<Vec<FicsG>> {
     //let input = File::open(fname)?;
     //let buffered = io::BufReader::new(input);
     let input = File::open(fname).expect("Open file");
@@ -282,7 +300,7 @@ fn read_games(fname: &str, min_elo: usize) -> io::Result<Vec<FicsG>> {
                 current_game.fens.extend(fens);
             }
         } else if line.is_empty() && in_moves_section {
-            if matches!(current_game.result, "1-0" | "0-1")
+            if (matches!(current_game.result, "1-0" | "0-1") || draws)
                 && current_game.white_elo >= min_elo
                 && current_game.black_elo >= min_elo
             {
@@ -357,12 +375,54 @@ fn write_games(fname: &str, l: &[FicsG]) -> Result<()> {
     Ok(())
 }
 
+fn write_book(games: &[FicsG], args: &Args, path: &str) -> std::result::Result<(), String> {
+    let mut stats = BookStats::new(args.book_depth);
+    for g in games {
+        let white_points = match g.result {
+            "1-0" => 2,
+            "0-1" => 0,
+            _ => 1,
+        };
+        stats.add_game(&g.moves, white_points);
+    }
+    if path.ends_with(".bin") {
+        let keys = match &args.book_keys {
+            Some(keys) => Keys::load(keys)?,
+            None => Keys::default(),
+        };
+        let book = stats.to_polyglot(keys, args.book_min_count);
+        book.save(path)?;
+        println!("output {} book entries to {path}", book.len());
+    } else {
+        let text = stats.to_text(args.book_min_count);
+        std::fs::write(path, &text).map_err(|e| format!("{path}: {e}"))?;
+        println!("output {} book lines to {path}", text.lines().count());
+    }
+    Ok(())
+}
+
 fn main() {
     let args = Args::parse();
+    if let Some(path) = &args.book {
+        let mut games = Vec::new();
+        for fname in &args.files {
+            println!("Processing {fname}");
+            match read_games(fname.as_str(), args.e, true) {
+                Err(m) => println!("error: {m}"),
+                Ok(l) => games.extend(l),
+            }
+        }
+        summarise_games(&games);
+        if let Err(m) = write_book(&games, &args, path) {
+            println!("error: {m}");
+        }
+        return;
+    }
+
     let mut games = Vec::new();
     for fname in args.files {
         println!("Processing {fname}");
-        match read_games(fname.as_str(), args.e) {
+        match read_games(fname.as_str(), args.e, false) {
             Err(m) => println!("error: {m}"),
             Ok(l) => {
                 let path = Path::new(&fname);
@@ -5,13 +5,17 @@
 
 use ::std::time::Instant;
 use clap::Parser;
+use clap::builder::PossibleValuesParser;
 use mateus::benchmark;
-use mateus::mgen::{Board, Move};
-use mateus::misc::str2move;
-use mateus::openings::library_moves;
+use mateus::mgen::{self, Board, Move};
+use mateus::openings::{self, Book, Library};
+use mateus::problems::{self, Stipulation};
+use mateus::strength::{self, Strength};
+use mateus::style::{self, Style};
+use mateus::trace::SearchTrace;
+use mateus::transposition::{self, Transpositions};
 use mateus::val::*;
 use mateus::{Game, SearchConstraints};
-use rand::random;
 use std::collections::hash_map::HashMap;
 use std::io;
 
@@ -42,6 +46,54 @@ struct Args {
     #[arg(short, long, default_value_t = String::from(ROOT_FEN))]
     ///fen board - start position
     f: String,
+    #[arg(long)]
+    ///Chess960 start position 0-959 (518 is the standard position) or "random" - replaces -f
+    chess960: Option<String>,
+    #[arg(long)]
+    ///solve problem in the -f position: mate (#n), helpmate (h#n) or selfmate (s#n)
+    problem: Option<String>,
+    #[arg(long, default_value_t = strength::MAX_SKILL)]
+    ///skill level 0-20 - limit strength (human-computer)
+    skill: u8,
+    #[arg(long)]
+    ///limit strength to elo - overrides skill level
+    elo: Option<u16>,
+    #[arg(long, default_value_t = String::from(style::DEFAULT.name), value_parser = PossibleValuesParser::new(style::NAMES))]
+    ///playing style
+    style: String,
+    #[arg(long)]
+    ///count the leaf nodes of the legal move tree (perft) of the given depth from the -f position, split per move
+    perft: Option<usize>,
+    #[arg(long)]
+    ///search the -f position and write the search tree to file - JSON (.json) or Graphviz DOT
+    trace: Option<String>,
+    #[arg(long, default_value_t = 4)]
+    ///max ply recorded in the search tree trace
+    trace_depth: usize,
+    #[arg(long, default_value_t = 10000)]
+    ///max nodes recorded in the search tree trace
+    trace_nodes: usize,
+    #[arg(long, default_value_t = transposition::DEFAULT_MB)]
+    ///transposition table size in MB
+    hash: usize,
+    #[arg(long)]
+    ///load transposition table from file before playing - overrides hash size
+    load_hash: Option<String>,
+    #[arg(long)]
+    ///save transposition table to file after playing
+    save_hash: Option<String>,
+    #[arg(long)]
+    ///opening book(s) replacing the compiled-in library - text books are merged, or one Polyglot book (.bin)
+    book: Vec<String>,
+    #[arg(long)]
+    ///Polyglot Random64 keys (text file with the 781 0x.. numbers) - replaces the built-in keys for --book
+    book_keys: Option<String>,
+    #[arg(long)]
+    ///book learning file - results of book moves, updated after each finished game
+    book_learn: Option<String>,
+    #[arg(long, default_value_t = openings::MAX_VARIETY)]
+    ///book variety 0-100 - 0 plays the highest weighted move, 100 picks in proportion to the weights
+    book_variety: u8,
 }
 
 fn pick_move(game: &mut Game, moves: &[Move]) -> Vec<(Move, i16)> {
@@ -63,13 +115,13 @@ fn pick_move(game: &mut Game, moves: &[Move]) -> Vec<(Move, i16)> {
                 println!();
             }
             _ => {
-                let Some((frm, to)) = str2move(s.as_str()) else {
+                if s.len() < 4 {
                     println!("Not valid");
                     continue;
-                };
+                }
                 let l: Vec<_> = moves
                     .iter()
-                    .filter(|m| (m.frm(), m.to()) == (frm, to))
+                    .filter(|m| game.board.uci_label(m).starts_with(s.as_str()))
                     .collect();
                 match l.len() {
                     0 => println!("Not legal"),
@@ -117,19 +169,27 @@ fn check_game_over(game: &Game, moves: &[Move], half_moves: isize) -> String {
     }
 }
 
-fn benchmark(verbose: bool, search_threshold: usize, tname: &str, tpos: &[(&str, &str)]) {
+fn benchmark(
+    verbose: bool,
+    search_threshold: usize,
+    hash_mb: usize,
+    tname: &str,
+    tpos: &[(&str, &str)],
+) {
     println!("{tname} Test - search threshold: {search_threshold}");
     let mut correct: Vec<usize> = vec![];
     let mut points: f64 = 0.0;
     let start = Instant::now();
     let mut n_searched: usize = 0;
     let sc = SearchConstraints::default().nodes(search_threshold);
+    let mut game = Game::with_hash(Board::default(), hash_mb);
     for (i, (fen, label)) in tpos.iter().enumerate() {
         let Ok(board) = Board::from_fen(fen) else {
             println!("Bad fen: {fen}");
             continue;
         };
-        let mut game = Game::new(board);
+        game.set_position(board);
+        game.ttable.clear();
         let moves = game.board.legal_moves();
 
         let (l, search_info) = game.score_moves(&moves, &sc, verbose);
@@ -156,11 +216,12 @@ fn benchmark(verbose: bool, search_threshold: usize, tname: &str, tpos: &[(&str,
             }
         }
         println!(
-            "Position {:>2}; Depth: {:>3}, Searched: {:>9}, Score: {score:>5 }, Move ({colour}): {best} = {clabel:>4 }; Expected: {label}\n",
+            "Position {:>2}; Depth: {:>3}, Searched: {:>9}, Score: {score:>5 }, Move ({colour}): {best} = {clabel:>4 }; Expected: {label}",
             i + 1,
             search_info.depth,
             search_info.nodes,
         );
+        println!("{search_info}\n");
         if (*label).contains(clabel.as_str()) {
             //if clabel.as_str() == *label {
             correct.push(i + 1);
@@ -168,7 +229,7 @@ fn benchmark(verbose: bool, search_threshold: usize, tname: &str, tpos: &[(&str,
         println!("Correct: {correct:?} {}/{}", correct.len(), tpos.len());
         println!("Points: {points}");
 
-        let dur = (Instant::now() - start).as_millis() as u128;
+        let dur = (Instant::now() - start).as_millis();
         println!("Time: {dur} ms => {} ms/position", dur / (i + 1) as u128);
         let speed = if let Some(speed) = (n_searched as u128).checked_div(dur) {
             speed as usize
@@ -182,49 +243,92 @@ fn benchmark(verbose: bool, search_threshold: usize, tname: &str, tpos: &[(&str,
     }
 }
 
+fn solve_problem(fen: &str, problem: &str) -> Result<(), String> {
+    let Some((stipulation, n)) = Stipulation::parse(problem) else {
+        return Err(format!("Bad problem stipulation: {problem}"));
+    };
+    let mut game = Game::new(Board::from_fen(fen)?);
+    println!("{game}");
+    let start = Instant::now();
+    let solutions = problems::solve(&mut game, stipulation, n);
+    for s in &solutions {
+        println!("{stipulation}{}: {s}", s.n);
+    }
+    let cooked = if problems::is_cooked(&solutions) {
+        " - cooked"
+    } else {
+        ""
+    };
+    println!(
+        "{stipulation}{n}: {} solution(s){cooked}; Time: {} ms",
+        solutions.len(),
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
 fn play(
     players: HashMap<Colour, bool>,
     verbose: bool,
     search_threshold: usize,
     half_moves: isize,
-    library_bypass: bool,
-    fen: &str,
-) -> Result<(), String> {
-    let mut game = Game::new(Board::from_fen(fen)?);
+    mut library: Option<&mut Library>,
+    game: &mut Game,
+    strength: Strength,
+) {
     println!("{game}");
     let mut tot = 0;
     let mut moves = game.board.legal_moves();
 
-    let sc = SearchConstraints::default().nodes(search_threshold);
+    let sc = strength.constrain(&SearchConstraints::default().nodes(search_threshold));
     let start = Instant::now();
     loop {
-        let msg = check_game_over(&game, &moves, half_moves);
+        let msg = check_game_over(game, &moves, half_moves);
         if !msg.is_empty() {
             println!("{msg}");
-            std::process::exit(0);
+            let white_result = match msg.split(' ').next() {
+                Some("1-0") => Some(1),
+                Some("0-1") => Some(-1),
+                Some("1/2-1/2") => Some(0),
+                _ => None, // unfinished
+            };
+            if let Some(library) = library
+                && let Some(r) = white_result
+                && let Err(m) = library.game_over(r)
+            {
+                println!("{m}");
+            }
+            return;
         }
 
         let l = if players[&game.board.turn] {
-            pick_move(&mut game, &moves)
+            pick_move(game, &moves)
         } else {
             // try library 1st - compute if not there
-            let lmoves = library_moves(game.board.hash);
-            if !library_bypass && !lmoves.is_empty() {
+            if let Some(m) = library.as_mut().and_then(|b| b.pick(&mut game.board)) {
                 if verbose {
-                    println!("#library moves from {}: {}", game.board.hash, lmoves.len());
-                    println!("{lmoves:?}");
+                    println!("#library moves from {}", game.board.hash);
                 };
-                let i = random::<u32>() % lmoves.len() as u32;
-                let (frm, to) = lmoves[i as usize];
-                if let Some(m) = moves.iter().find(|m| (m.frm(), m.to()) == (frm, to)) {
-                    println!("Library Move {m} ");
-                    vec![(*m, 0i16)]
-                } else {
-                    panic!("Not a valid library move")
-                }
+                println!("Library Move {m} ");
+                vec![(m, 0i16)]
             } else {
-                let (l, search_info) = game.score_moves(&moves, &sc, verbose);
+                let (mut l, search_info) = game.score_moves(&moves, &sc, verbose);
                 tot += search_info.nodes;
+                if verbose {
+                    println!("{search_info}");
+                }
+                let i = strength.pick(&l);
+                if i > 0 {
+                    if verbose {
+                        println!(
+                            "Skill {} picks {} over {}",
+                            strength.skill(),
+                            l[i].0,
+                            l[0].0
+                        );
+                    }
+                    l[..=i].rotate_right(1);
+                }
                 l
             }
         };
@@ -291,29 +395,176 @@ fn get_input() -> String {
     String::from(s.trim())
 }
 
+fn perft(fen: &str, depth: usize) -> Result<(), String> {
+    let mut board = Board::from_fen(fen).map_err(|m| format!("Bad fen: {m}"))?;
+    let start = Instant::now();
+    let divide = board.divide(depth);
+    for (m, n) in &divide {
+        println!("{}: {n}", board.uci_label(m));
+    }
+    let n: usize = divide.iter().map(|(_, n)| n).sum();
+    let ms = start.elapsed().as_millis();
+    println!(
+        "Moves: {}; Nodes: {n}; Time: {ms} ms; Nodes/s: {}",
+        divide.len(),
+        n as u128 * 1000 / ms.max(1)
+    );
+    Ok(())
+}
+
+fn trace_search(
+    fen: &str,
+    search_threshold: usize,
+    hash_mb: usize,
+    trace: SearchTrace,
+    path: &str,
+) -> Result<(), String> {
+    let board = Board::from_fen(fen).map_err(|m| format!("Bad fen: {m}"))?;
+    let mut game = Game::with_hash(board, hash_mb);
+    game.trace = Some(trace);
+    let moves = game.board.legal_moves();
+    let sc = SearchConstraints::default().nodes(search_threshold);
+    let (l, search_info) = game.score_moves(&moves, &sc, false);
+    let Some(&(best, score)) = l.first() else {
+        return Err("No legal moves".to_string());
+    };
+    let trace = game.trace.take().unwrap();
+    trace
+        .write(path)
+        .map_err(|e| format!("Failed to write {path}: {e}"))?;
+    println!(
+        "Depth: {}, Searched: {}, Score: {score}, Move: {best}",
+        search_info.depth, search_info.nodes
+    );
+    println!(
+        "Trace of last iteration: {} nodes written to {path}",
+        trace.nodes.len()
+    );
+    Ok(())
+}
+
+fn load_library(args: &Args) -> Result<Library, String> {
+    let book = if args.book.is_empty() {
+        Book::default()
+    } else {
+        let book = Book::load(&args.book, args.book_keys.as_deref())?;
+        println!(
+            "Loaded {} book positions from {}",
+            book.len(),
+            args.book.join(", ")
+        );
+        book
+    };
+    let library = Library::new(book).variety(args.book_variety);
+    match &args.book_learn {
+        Some(path) => library.learn_file(path),
+        None => Ok(library),
+    }
+}
+
+/// FEN of the Chess960 start position - a number or "random"
+fn chess960(position: &str) -> Result<String, String> {
+    let n = match position {
+        "random" => rand::random_range(0..960),
+        s => match s.parse::<usize>() {
+            Ok(n) if n < 960 => n,
+            _ => {
+                return Err(format!(
+                    "Bad Chess960 position: {s} - expected 0-959 or random"
+                ));
+            }
+        },
+    };
+    let fen = mgen::chess960_fen(n);
+    println!("Chess960 position {n}: {fen}");
+    Ok(fen)
+}
+
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let Some(position) = &args.chess960 {
+        match chess960(position) {
+            Ok(fen) => args.f = fen,
+            Err(m) => {
+                println!("{m}");
+                return;
+            }
+        }
+    }
 
-    if args.k > 0 {
+    if let Some(depth) = args.perft {
+        if let Err(m) = perft(args.f.as_str(), depth) {
+            println!("{m}");
+        }
+    } else if let Some(path) = args.trace {
+        let trace = SearchTrace::new(args.trace_depth, args.trace_nodes);
+        if let Err(m) = trace_search(args.f.as_str(), args.n, args.hash, trace, path.as_str()) {
+            println!("{m}");
+        }
+    } else if let Some(problem) = args.problem {
+        if let Err(m) = solve_problem(args.f.as_str(), problem.as_str()) {
+            println!("{m}");
+        }
+    } else if args.k > 0 {
         match args.k {
-            1 => benchmark(args.v, args.n, "Bratko-Kopec", &benchmark::BRATKO_KOPEC),
-            2 => benchmark(args.v, args.n, "Kaufman", &benchmark::KAUFMAN),
-            3 => benchmark(args.v, args.n, "Lasker", &benchmark::LASKER),
-            4 => benchmark(args.v, args.n, "Nolot", &benchmark::NOLOT),
-            5 => benchmark(args.v, args.n, "CCR One Hour", &benchmark::CCR),
+            1 => benchmark(
+                args.v,
+                args.n,
+                args.hash,
+                "Bratko-Kopec",
+                &benchmark::BRATKO_KOPEC,
+            ),
+            2 => benchmark(args.v, args.n, args.hash, "Kaufman", &benchmark::KAUFMAN),
+            3 => benchmark(args.v, args.n, args.hash, "Lasker", &benchmark::LASKER),
+            4 => benchmark(args.v, args.n, args.hash, "Nolot", &benchmark::NOLOT),
+            5 => benchmark(args.v, args.n, args.hash, "CCR One Hour", &benchmark::CCR),
             6 => benchmark(
                 args.v,
                 args.n,
+                args.hash,
                 "Eigenmann Rapid Engine Test",
                 &benchmark::ERET,
             ),
-            7 => benchmark(args.v, args.n, "BT-2450", &benchmark::BT2450),
-            _ => benchmark(args.v, args.n, "BT-2630", &benchmark::BT2630),
+            7 => benchmark(args.v, args.n, args.hash, "BT-2450", &benchmark::BT2450),
+            _ => benchmark(args.v, args.n, args.hash, "BT-2630", &benchmark::BT2630),
         }
     } else {
         let players = HashMap::from([(Colour::white(), args.w), (Colour::black(), args.b)]);
-        if let Err(m) = play(players, args.v, args.n, args.m, args.l, args.f.as_str()) {
-            println!("Bad fen: {m}");
+        let strength = match args.elo {
+            Some(elo) => Strength::from_elo(elo),
+            None => Strength::new(args.skill),
+        };
+        match Board::from_fen(args.f.as_str()) {
+            Ok(board) => {
+                let mut game = Game::with_hash(board, args.hash);
+                game.set_style(Style::from_name(&args.style).unwrap_or_default());
+                if let Some(path) = &args.load_hash {
+                    match Transpositions::load(path) {
+                        Ok(tt) => game.ttable = tt,
+                        Err(m) => {
+                            println!("{m}");
+                            return;
+                        }
+                    }
+                }
+                let mut library = match load_library(&args) {
+                    Ok(library) => library,
+                    Err(m) => {
+                        println!("{m}");
+                        return;
+                    }
+                };
+                let library = (!args.l).then_some(&mut library);
+                play(
+                    players, args.v, args.n, args.m, library, &mut game, strength,
+                );
+                if let Some(path) = args.save_hash
+                    && let Err(m) = game.ttable.save(&path)
+                {
+                    println!("{m}");
+                }
+            }
+            Err(m) => println!("Bad fen: {m}"),
         }
     }
 }
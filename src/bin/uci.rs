@@ -13,19 +13,31 @@
 // Engine communicates with GUI via stdin and stdout
 // https://www.wbec-ridderkerk.nl/html/UCIProtocol.html
 //
+// Custom commands: "savehash <file>" & "loadhash <file>" - persist the transposition table.
+// A loaded table is kept by the next ucinewgame, which otherwise clears the table.
+//
+// Opening book: OwnBook enables the book, BookFile replaces the compiled-in library with a
+// text book or a Polyglot .bin book - BookKeys optionally replaces the built-in Polyglot
+// Random64 keys.
+// BookLearning names a file of learned book results, updated at ucinewgame & quit. UCI does
+// not report the game result - it is inferred from the last search score, see LEARN_MARGIN.
+// Games without a search, e.g. ended in the book, are not learned from.
+//
+// Chess960: UCI_Chess960 switches castling moves to king takes rook notation, e.g. e1h1.
+// Positions are given as X-FEN or Shredder-FEN.
+//
 // TODO: handle more commands, e.g. "stop", "go ponder"
 
 use clap::Parser;
 use mateus::{
     Game, SearchConstraints,
-    mgen::{Board, Move},
-    misc::str2move,
-    openings::library_moves,
-    val,
-    val::I2SQ,
+    mgen::Board,
+    openings::{self, Book, BookLearning, Library},
+    strength::{self, Strength},
+    style::{self, Style},
+    transposition::{self, Transpositions},
     val::ROOT_FEN,
 };
-use rand::random;
 use std::time::Duration;
 
 #[derive(Parser, Debug)]
@@ -36,22 +48,12 @@ struct Args {
     n: usize,
 }
 
-fn move2uci_string(m: &Move) -> String {
-    let p = match m.promote_kind() {
-        val::QUEEN => "q",
-        val::ROOK => "r",
-        val::BISHOP => "b",
-        val::KNIGHT => "n",
-        _ => "",
-    };
-    format!("{}{}{p}", I2SQ[m.frm() as usize], I2SQ[m.to() as usize])
-}
-
-fn handle_position(game: &mut Game, parts: &[&str]) {
+/// "position startpos|fen <fen> moves ..." - with UCI_Chess960 castling is king takes rook
+fn handle_position(game: &mut Game, parts: &[&str], chess960: bool) {
     let mut i = 0;
 
     if parts.get(i) == Some(&"startpos") {
-        *game = Game::new(Board::from_fen(ROOT_FEN).unwrap());
+        game.set_position(Board::from_fen(ROOT_FEN).unwrap());
         i += 1;
     } else if parts.get(i) == Some(&"fen") {
         i += 1;
@@ -61,50 +63,68 @@ fn handle_position(game: &mut Game, parts: &[&str]) {
             i += 1;
         }
         let fen = parts[fen_start..i].join(" ");
-        *game = Game::new(Board::from_fen(&fen).expect("Invalid FEN"));
+        game.set_position(Board::from_fen(&fen).expect("Invalid FEN"));
     }
 
     if parts.get(i) == Some(&"moves") {
         i += 1;
     }
 
+    game.board.chess960 |= chess960;
     for s in &parts[i..] {
-        let Some((frm, to)) = str2move(s) else {
-            continue;
-        };
-        let promo_char = s.chars().nth(4);
-
-        // Find move in legal moves
         // TODO - trust that the move is legal...
-        if let Some(mv) = game.board.legal_moves().iter().find(|m| {
-            (m.frm(), m.to()) == (frm, to)
-                && (promo_char.is_none() || m.promote_kind() == match_promo(promo_char.unwrap()))
-        }) {
-            game.make_move(*mv);
+        if let Some(m) = game.board.parse_uci_move(s) {
+            game.make_move(m);
         }
     }
 }
 
-// Helper for promotion piece mapping
-fn match_promo(c: char) -> u8 {
-    match c {
-        'q' => val::QUEEN,
-        'r' => val::ROOK,
-        'b' => val::BISHOP,
-        'n' => val::KNIGHT,
-        _ => 0,
+/// last search score (centipawns) that counts as a won / lost game for book learning
+const LEARN_MARGIN: i16 = 300;
+
+/// load the book in BookFile - the compiled-in library if not set
+fn load_book(file: &str, keys: &str) -> Book {
+    if file.is_empty() {
+        return Book::default();
+    }
+    let keys = (!keys.is_empty()).then_some(keys);
+    match Book::load(&[file.to_string()], keys) {
+        Ok(book) => {
+            println!(
+                "info string Loaded {} book positions from {file}",
+                book.len()
+            );
+            book
+        }
+        Err(m) => {
+            println!("info string {m}");
+            Book::default()
+        }
     }
 }
 
-fn pick_library_move(lmoves: &[(u8, u8)]) -> Option<(u8, u8)> {
-    if lmoves.is_empty() {
-        return None;
+fn load_library(file: &str, keys: &str, learning: &str, variety: u8) -> Library {
+    let mut library = Library::new(load_book(file, keys)).variety(variety);
+    if !learning.is_empty() {
+        match BookLearning::load(learning) {
+            Ok(l) => {
+                library.learning = l;
+                library.learn_path = Some(learning.to_string());
+            }
+            Err(m) => println!("info string {m}"),
+        }
     }
-    let i = random::<u32>() % lmoves.len() as u32;
-    Some(lmoves[i as usize])
+    library
 }
 
-fn handle_go(game: &mut Game, sc: &mut SearchConstraints, parts: &[&str], overhead: u64) {
+fn handle_go(
+    game: &mut Game,
+    sc: &mut SearchConstraints,
+    parts: &[&str],
+    overhead: u64,
+    strength: Strength,
+    library: Option<&mut Library>,
+) -> Option<i16> {
     let mut wtime: Option<u64> = None;
     let mut btime: Option<u64> = None;
     let mut winc: Option<u64> = None;
@@ -190,34 +210,75 @@ fn handle_go(game: &mut Game, sc: &mut SearchConstraints, parts: &[&str], overhe
     let moves = game.board.legal_moves();
     if moves.is_empty() {
         println!("bestmove (none)");
-        return;
+        return None;
     }
 
-    let lmoves = library_moves(game.board.hash);
-    println!("info string lib moves: {}", lmoves.len());
-
-    let l = if let Some((frm, to)) = pick_library_move(lmoves) {
-        if let Some(m) = moves.iter().find(|m| (m.frm(), m.to()) == (frm, to)) {
-            println!("info string Library hit: {m}");
-            vec![(*m, 0i16)]
-        } else {
-            game.score_moves(&moves, sc, false).0
-        }
+    let sc = strength.constrain(sc);
+    let library_move = library.and_then(|b| b.pick(&mut game.board));
+    let (l, score) = if let Some(m) = library_move {
+        println!("info string Library hit: {m}");
+        (vec![(m, 0i16)], None)
     } else {
-        game.score_moves(&moves, sc, false).0
+        let (l, info) = game.score_moves(&moves, &sc, false);
+        println!(
+            "info depth {} nodes {} time {} hashfull {}",
+            info.depth,
+            info.nodes,
+            info.time.as_millis(),
+            info.hashfull
+        );
+        let score = l.first().map(|&(_, score)| {
+            if game.board.turn.is_white() {
+                score
+            } else {
+                -score
+            }
+        });
+        (l, score)
     };
 
-    if let Some((best_move, _score)) = l.get(0) {
-        println!("bestmove {}", move2uci_string(best_move));
+    if let Some((best_move, _score)) = l.get(strength.pick(&l)) {
+        println!("bestmove {}", game.board.uci_label(best_move));
     } else {
         println!("bestmove (none)");
     }
+    score
+}
+
+/// learn from the book moves of the last game - the result is inferred from the last
+/// search score (white's view). Without a search there is nothing to learn from.
+fn learn_game(library: &mut Library, last_score: Option<i16>) {
+    let Some(score) = last_score else {
+        library.new_game();
+        return;
+    };
+    let white_result = match score {
+        s if s >= LEARN_MARGIN => 1,
+        s if s <= -LEARN_MARGIN => -1,
+        _ => 0,
+    };
+    if let Err(m) = library.game_over(white_result) {
+        println!("info string {m}");
+    }
 }
 
 fn uci_loop(mut sc: SearchConstraints) {
     let mut game = Game::new(Board::from_fen(ROOT_FEN).unwrap());
     let mut input = String::new();
     let mut move_overhead = 10; // Default 10ms
+    let mut skill_level = strength::MAX_SKILL;
+    let mut limit_strength = false;
+    let mut uci_elo = strength::MAX_ELO;
+    let mut style = Style::default();
+    let mut own_book = true;
+    let mut book_file = String::new();
+    let mut book_keys = String::new();
+    let mut book_learning = String::new();
+    let mut book_variety = openings::MAX_VARIETY;
+    let mut library = Library::new(Book::default());
+    let mut last_score = None;
+    let mut chess960 = false;
+    let mut hash_loaded = false; // kept by the next ucinewgame
 
     loop {
         input.clear();
@@ -233,25 +294,101 @@ fn uci_loop(mut sc: SearchConstraints) {
                 println!("id author Jesper Olsen");
                 println!("option name Move Overhead type spin default 10 min 0 max 5000");
                 println!("option name Threads type spin default 1 min 1 max 128");
-                println!("option name Hash type spin default 16 min 1 max 1024");
+                println!(
+                    "option name Hash type spin default {} min {} max {}",
+                    transposition::DEFAULT_MB,
+                    transposition::MIN_MB,
+                    transposition::MAX_MB
+                );
                 println!("option name SyzygyPath type string default <empty>");
                 println!("option name UCI_ShowWDL type check default false");
+                println!(
+                    "option name Skill Level type spin default {0} min 0 max {0}",
+                    strength::MAX_SKILL
+                );
+                println!(
+                    "option name Style type combo default {}{}",
+                    style::DEFAULT.name,
+                    style::NAMES.map(|s| format!(" var {s}")).concat()
+                );
+                println!("option name OwnBook type check default true");
+                println!("option name BookFile type string default <empty>");
+                println!("option name BookKeys type string default <empty>");
+                println!("option name BookLearning type string default <empty>");
+                println!(
+                    "option name BookVariety type spin default {0} min 0 max {0}",
+                    openings::MAX_VARIETY
+                );
+                println!("option name UCI_Chess960 type check default false");
+                println!("option name UCI_LimitStrength type check default false");
+                println!(
+                    "option name UCI_Elo type spin default {1} min {0} max {1}",
+                    strength::MIN_ELO,
+                    strength::MAX_ELO
+                );
                 println!("uciok");
             }
             "isready" => println!("readyok"),
             "ucinewgame" => {
-                game = Game::new(Board::from_fen(ROOT_FEN).unwrap());
-                game.ttable.clear();
+                learn_game(&mut library, last_score);
+                last_score = None;
+                game.set_position(Board::from_fen(ROOT_FEN).unwrap());
+                if !hash_loaded {
+                    game.ttable.clear();
+                }
+                hash_loaded = false;
             }
             "position" => {
                 // Example: position startpos moves e2e4 e7e5
-                handle_position(&mut game, &parts[1..]);
+                handle_position(&mut game, &parts[1..], chess960);
             }
             "go" => {
                 // Example: go depth 10 or go movetime 5000
-                handle_go(&mut game, &mut sc, &parts[1..], move_overhead);
+                let strength = if limit_strength {
+                    Strength::from_elo(uci_elo)
+                } else {
+                    Strength::new(skill_level)
+                };
+                game.set_style(style);
+                let library = own_book.then_some(&mut library);
+                if let Some(score) = handle_go(
+                    &mut game,
+                    &mut sc,
+                    &parts[1..],
+                    move_overhead,
+                    strength,
+                    library,
+                ) {
+                    last_score = Some(score);
+                }
+            }
+            "savehash" => {
+                // custom command: savehash <file>
+                let path = parts[1..].join(" ");
+                match game.ttable.save(&path) {
+                    Ok(()) => println!("info string Saved hash table to {path}"),
+                    Err(m) => println!("info string {m}"),
+                }
+            }
+            "loadhash" => {
+                // custom command: loadhash <file>
+                let path = parts[1..].join(" ");
+                match Transpositions::load(&path) {
+                    Ok(tt) => {
+                        game.ttable = tt;
+                        hash_loaded = true;
+                        println!(
+                            "info string Loaded hash table from {path} ({} MB)",
+                            game.ttable.size_mb()
+                        );
+                    }
+                    Err(m) => println!("info string {m}"),
+                }
+            }
+            "quit" => {
+                learn_game(&mut library, last_score);
+                break;
             }
-            "quit" => break,
             "d" | "print" => {
                 println!("{}", game.board);
             }
@@ -262,6 +399,7 @@ fn uci_loop(mut sc: SearchConstraints) {
                 {
                     let option_name = parts[name_idx + 1..val_idx].join(" ").to_lowercase();
                     let option_value = parts.get(val_idx + 1);
+                    let option_string = parts[val_idx + 1..].join(" ");
                     match option_name.as_str() {
                         "move overhead" => {
                             if let Some(v) = option_value.and_then(|s| s.parse::<u64>().ok()) {
@@ -276,7 +414,58 @@ fn uci_loop(mut sc: SearchConstraints) {
                             }
                         }
                         "hash" => {
-                            // TODO handle TT size here (optional)
+                            if let Some(v) = option_value.and_then(|s| s.parse::<usize>().ok()) {
+                                game.ttable.resize(v);
+                            }
+                        }
+                        "skill level" => {
+                            if let Some(v) = option_value.and_then(|s| s.parse::<u8>().ok()) {
+                                skill_level = v;
+                            }
+                        }
+                        "style" => {
+                            if let Some(s) = option_value.and_then(|s| Style::from_name(s)) {
+                                style = s;
+                            }
+                        }
+                        "uci_chess960" => {
+                            if let Some(v) = option_value.and_then(|s| s.parse::<bool>().ok()) {
+                                chess960 = v;
+                            }
+                        }
+                        "uci_limitstrength" => {
+                            if let Some(v) = option_value.and_then(|s| s.parse::<bool>().ok()) {
+                                limit_strength = v;
+                            }
+                        }
+                        "uci_elo" => {
+                            if let Some(v) = option_value.and_then(|s| s.parse::<u16>().ok()) {
+                                uci_elo = v;
+                            }
+                        }
+                        "ownbook" => {
+                            if let Some(v) = option_value.and_then(|s| s.parse::<bool>().ok()) {
+                                own_book = v;
+                            }
+                        }
+                        "bookfile" | "bookkeys" | "booklearning" => {
+                            let path = match option_string.as_str() {
+                                "<empty>" => String::new(),
+                                s => s.to_string(),
+                            };
+                            match option_name.as_str() {
+                                "bookfile" => book_file = path,
+                                "bookkeys" => book_keys = path,
+                                _ => book_learning = path,
+                            }
+                            library =
+                                load_library(&book_file, &book_keys, &book_learning, book_variety);
+                        }
+                        "bookvariety" => {
+                            if let Some(v) = option_value.and_then(|s| s.parse::<u8>().ok()) {
+                                book_variety = v;
+                                library.variety = v.min(openings::MAX_VARIETY);
+                            }
                         }
                         "syzygypath" => {
                             if let Some(path) = option_value {
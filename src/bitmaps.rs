@@ -1,54 +1,191 @@
+use crate::magics_generated::{BISHOP_MAGICS, ROOK_MAGICS};
+use crate::square::{File, Rank, Square};
 use crate::val::Colour;
+use std::fmt;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Shl, Shr};
 
-pub static BM_BLOCKED: [[u64; 64]; 64] = bm_queen_blockers();
 pub static BM_QUEEN_MOVES: [u64; 64] = bm_queen_moves();
 pub static BM_BISHOP_MOVES: [u64; 64] = bm_bishop_moves();
 pub static BM_ROOK_MOVES: [u64; 64] = bm_rook_moves();
 pub static BM_KNIGHT_MOVES: [u64; 64] = bm_knight_moves();
 pub static BM_KING_MOVES: [u64; 64] = bm_king_moves();
 pub static BM_PAWN_CAPTURES: [[u64; 64]; 2] = bm_pawn_captures();
-pub const ROW2: u64 = 0b00000010_00000010_00000010_00000010_00000010_00000010_00000010_00000010;
-pub const ROW3: u64 = 0b00000100_00000100_00000100_00000100_00000100_00000100_00000100_00000100;
-pub const ROW7: u64 = 0b01000000_01000000_01000000_01000000_01000000_01000000_01000000_01000000;
-pub const ROW6: u64 = 0b00100000_00100000_00100000_00100000_00100000_00100000_00100000_00100000;
+pub const ROW1: u64 = Rank::FIRST.bb().0;
+pub const ROW8: u64 = Rank::EIGHTH.bb().0;
+pub const ROW2: u64 = Rank::new(1).bb().0;
+pub const ROW3: u64 = Rank::new(2).bb().0;
+pub const ROW7: u64 = Rank::new(6).bb().0;
+pub const ROW6: u64 = Rank::new(5).bb().0;
+
+// Sliding piece attacks - fancy magic bitboards. The blockers on a slider's rays
+// are multiplied by a magic number, and the top bits of the product index the
+// attack table of the square. The magic numbers are generated by bin/build_magics.rs,
+// the tables at compile time.
+pub const ROOK_DIRECTIONS: [(isize, isize); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+pub const BISHOP_DIRECTIONS: [(isize, isize); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const ROOK_TABLE_SIZE: usize = table_size(&ROOK_DIRECTIONS);
+const SLIDER_TABLE_SIZE: usize = ROOK_TABLE_SIZE + table_size(&BISHOP_DIRECTIONS);
+static ROOK_MAGIC: [Magic; 64] = magics(&ROOK_DIRECTIONS, &ROOK_MAGICS, 0);
+static BISHOP_MAGIC: [Magic; 64] = magics(&BISHOP_DIRECTIONS, &BISHOP_MAGICS, ROOK_TABLE_SIZE);
+#[allow(long_running_const_eval)] // ~100k table entries
+static SLIDER_ATTACKS: [u64; SLIDER_TABLE_SIZE] = slider_attacks_table();
+
+#[derive(Debug, Clone, Copy)]
+pub struct Magic {
+    pub mask: u64, // squares that can block the slider - board edges excluded
+    pub magic: u64,
+    pub shift: u32,
+    pub offset: usize, // start of the square's attacks in SLIDER_ATTACKS
+}
 
-///valid moves from a given square assuming one square is blocked..
-const fn bm_queen_blockers() -> [[u64; 64]; 64] {
-    let mut bm = [[0; 64]; 64];
+impl Magic {
+    #[inline(always)]
+    pub const fn index(&self, occupied: u64) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
 
-    let mut frm = 0;
-    while frm < 64 {
-        let mut blocked = 0;
-        while blocked < 64 {
-            if frm != blocked {
-                bm[frm][blocked] = bm_queen_moves_bb(frm, blocked);
+/// squares attacked by a rook on frm
+#[inline(always)]
+pub const fn rook_attacks(frm: Square, occupied: Bitboard) -> Bitboard {
+    Bitboard(SLIDER_ATTACKS[ROOK_MAGIC[frm.as_usize()].index(occupied.0)])
+}
+
+/// squares attacked by a bishop on frm
+#[inline(always)]
+pub const fn bishop_attacks(frm: Square, occupied: Bitboard) -> Bitboard {
+    Bitboard(SLIDER_ATTACKS[BISHOP_MAGIC[frm.as_usize()].index(occupied.0)])
+}
+
+/// squares attacked by a queen on frm
+#[inline(always)]
+pub const fn queen_attacks(frm: Square, occupied: Bitboard) -> Bitboard {
+    Bitboard(rook_attacks(frm, occupied).0 | bishop_attacks(frm, occupied).0)
+}
+
+/// squares on the line through a and b, a and b excluded - none unless they share a line
+pub const fn line(a: Square, b: Square) -> Bitboard {
+    let (i, j) = (a.as_usize(), b.as_usize());
+    if BM_ROOK_MOVES[i] & 1 << j != 0 {
+        Bitboard(BM_ROOK_MOVES[i] & BM_ROOK_MOVES[j])
+    } else if BM_BISHOP_MOVES[i] & 1 << j != 0 {
+        Bitboard(BM_BISHOP_MOVES[i] & BM_BISHOP_MOVES[j])
+    } else {
+        Bitboard::EMPTY
+    }
+}
+
+/// squares strictly between a and b - none unless they share a line
+pub const fn between(a: Square, b: Square) -> Bitboard {
+    let (i, j) = (a.as_usize(), b.as_usize());
+    if BM_ROOK_MOVES[i] & 1 << j != 0 {
+        Bitboard(rook_attacks(a, b.bb()).0 & rook_attacks(b, a.bb()).0)
+    } else if BM_BISHOP_MOVES[i] & 1 << j != 0 {
+        Bitboard(bishop_attacks(a, b.bb()).0 & bishop_attacks(b, a.bb()).0)
+    } else {
+        Bitboard::EMPTY
+    }
+}
+
+/// squares attacked along the directions - the rays stop at the first occupied square.
+/// Slow - used to build the tables
+pub const fn slider_attacks(frm: usize, occupied: u64, directions: &[(isize, isize)]) -> u64 {
+    let mut b = 0;
+    let mut d = 0;
+    while d < directions.len() {
+        let (dx, dy) = directions[d];
+        let mut x = (frm / 8) as isize + dx;
+        let mut y = (frm % 8) as isize + dy;
+        while x >= 0 && x < 8 && y >= 0 && y < 8 {
+            b |= 1 << (x * 8 + y);
+            if occupied & 1 << (x * 8 + y) != 0 {
+                break;
             }
-            blocked += 1;
+            x += dx;
+            y += dy;
+        }
+        d += 1;
+    }
+    b
+}
+
+/// squares along the directions that can block a slider - the last square of a ray never blocks
+pub const fn slider_mask(frm: usize, directions: &[(isize, isize)]) -> u64 {
+    let mut b = 0;
+    let mut d = 0;
+    while d < directions.len() {
+        let (dx, dy) = directions[d];
+        let mut x = (frm / 8) as isize + dx;
+        let mut y = (frm % 8) as isize + dy;
+        while x + dx >= 0 && x + dx < 8 && y + dy >= 0 && y + dy < 8 {
+            b |= 1 << (x * 8 + y);
+            x += dx;
+            y += dy;
         }
+        d += 1;
+    }
+    b
+}
+
+const fn table_size(directions: &[(isize, isize)]) -> usize {
+    let mut n = 0;
+    let mut frm = 0;
+    while frm < 64 {
+        n += 1 << slider_mask(frm, directions).count_ones();
         frm += 1;
     }
-    bm
+    n
 }
 
-// set_bit k: b |= 1<<k
-// clear_bit k: b &= !(1<<k)
-// is_set k: b & 1<<k
-
-pub fn bm_display(b: u64) {
-    println!();
-    for y in (0..8).rev() {
-        print!("{} ", y + 1);
-        for x in 0..8 {
-            let x = if b & 1 << ((7 - x) * 8 + y) != 0 {
-                'x'
-            } else {
-                '.'
-            };
-            print!("{}", x);
+const fn magics(directions: &[(isize, isize)], numbers: &[u64; 64], offset: usize) -> [Magic; 64] {
+    let mut m = [Magic {
+        mask: 0,
+        magic: 0,
+        shift: 0,
+        offset: 0,
+    }; 64];
+    let mut offset = offset;
+    let mut frm = 0;
+    while frm < 64 {
+        let mask = slider_mask(frm, directions);
+        m[frm] = Magic {
+            mask,
+            magic: numbers[frm],
+            shift: 64 - mask.count_ones(),
+            offset,
+        };
+        offset += 1 << mask.count_ones();
+        frm += 1;
+    }
+    m
+}
+
+const fn slider_attacks_table() -> [u64; SLIDER_TABLE_SIZE] {
+    let mut table = [0; SLIDER_TABLE_SIZE];
+    let mut frm = 0;
+    while frm < 64 {
+        add_slider_attacks(&mut table, &ROOK_MAGIC[frm], frm, &ROOK_DIRECTIONS);
+        add_slider_attacks(&mut table, &BISHOP_MAGIC[frm], frm, &BISHOP_DIRECTIONS);
+        frm += 1;
+    }
+    table
+}
+
+/// attacks for all blocker subsets of the mask - enumerated with the carry-rippler trick
+const fn add_slider_attacks(
+    table: &mut [u64],
+    magic: &Magic,
+    frm: usize,
+    directions: &[(isize, isize)],
+) {
+    let mut occupied = 0u64;
+    loop {
+        table[magic.index(occupied)] = slider_attacks(frm, occupied, directions);
+        occupied = occupied.wrapping_sub(magic.mask) & magic.mask;
+        if occupied == 0 {
+            break;
         }
-        println!();
     }
-    println!("  ABCDEFGH");
 }
 
 const fn bm_queen_moves() -> [u64; 64] {
@@ -109,85 +246,6 @@ const fn bm_bishop_moves_from(frm: usize) -> u64 {
     b
 }
 
-///queen moves that are valid assuming assuming blocked square
-const fn bm_queen_moves_bb(frm: usize, blocked: usize) -> u64 {
-    let mut b = 0;
-
-    let frm_x = frm / 8;
-    let frm_y = frm % 8;
-    let blocked_x = blocked / 8;
-    let blocked_y = blocked % 8;
-
-    if frm_x == blocked_x {
-        if frm_y < blocked_y {
-            // rook blocked N
-            let mut i = blocked + 1;
-            while i % 8 > 0 {
-                b |= 1 << i;
-                i += 1;
-            }
-        } else {
-            // rook blocked S
-            let mut i = blocked as isize - 1;
-            while i % 8 < frm_y as isize && i >= 0 {
-                b |= 1 << i;
-                i -= 1;
-            }
-        }
-    } else if frm_y == blocked_y {
-        if frm_x < blocked_x {
-            // rook blocked W
-            let mut i = blocked + 8;
-            while i < 64 {
-                b |= 1 << i;
-                i += 8;
-            }
-        } else {
-            // rook blocked E
-            let mut i = blocked as isize - 8;
-            while i >= 0 {
-                b |= 1 << i;
-                i -= 8;
-            }
-        }
-    } else if frm_x.abs_diff(blocked_x) == frm_y.abs_diff(blocked_y) {
-        if frm_x > blocked_x {
-            if frm_y < blocked_y {
-                // bishop blocked NE
-                let mut i = blocked as isize - 7;
-                //while i % 8 <= 7 && i >= 0 {
-                while i % 8 > frm_y as isize && i >= 0 {
-                    b |= 1 << i;
-                    i -= 7;
-                }
-            } else {
-                // bishop blocked SE
-                let mut i = blocked as isize - 9;
-                //while i % 8 >= 0 && i >= 0 {
-                while i % 8 < frm_y as isize && i >= 0 {
-                    b |= 1 << i;
-                    i -= 9;
-                }
-            }
-        } else if frm_y % 8 < blocked_y % 8 {
-            //bishop blocked NW
-            let mut i = blocked + 9;
-            while i % 8 > frm_y && i < 64 {
-                b |= 1 << i;
-                i += 9;
-            }
-        } else {
-            //bishop blocked SW
-            let mut i = blocked + 7;
-            while i % 8 < frm_y && i < 64 {
-                b |= 1 << i;
-                i += 7;
-            }
-        }
-    }
-    b
-}
-
 const fn bm_pawn_captures() -> [[u64; 64]; 2] {
     let mut bm = [[0u64; 64]; 2];
     let mut i = 0;
@@ -201,10 +259,10 @@ const fn bm_pawn_captures() -> [[u64; 64]; 2] {
 
 const fn bm_white_pawn_captures_from(frm: usize) -> u64 {
     let mut b = 0;
-    if frm < 56 && (frm + 9) % 8 != 0 {
+    if frm < 56 && !(frm + 9).is_multiple_of(8) {
         b |= 1 << (frm + 9)
     }
-    if frm > 7 && (frm - 7) % 8 != 0 {
+    if frm > 7 && !(frm - 7).is_multiple_of(8) {
         b |= 1 << (frm - 7)
     }
     b
@@ -212,10 +270,10 @@ const fn bm_white_pawn_captures_from(frm: usize) -> u64 {
 
 const fn bm_black_pawn_captures_from(frm: usize) -> u64 {
     let mut b = 0;
-    if frm % 8 != 0 && frm < 56 {
+    if !frm.is_multiple_of(8) && frm < 56 {
         b |= 1 << (frm + 7)
     }
-    if frm % 8 != 0 && frm >= 9 {
+    if !frm.is_multiple_of(8) && frm >= 9 {
         b |= 1 << (frm - 9)
     }
     b
@@ -312,27 +370,250 @@ const fn bm_set_squares(rmoves: &[(isize, isize)]) -> [u64; 64] {
     bm
 }
 
-pub const fn bm2arr(bm: u64) -> ([u8; 64], usize) {
-    let mut b = bm;
-    let mut out = [0u8; 64];
-    let mut n = 0;
+/// indices of the set bits
+#[inline(always)]
+pub fn bits(mut bm: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        (bm != 0).then(|| {
+            let i = bm.trailing_zeros() as usize;
+            bm &= bm - 1;
+            i
+        })
+    })
+}
+
+/// A set of squares - a typed u64 bitmap, see Square for the bit order.
+/// Converts to and from the u64 bitmaps used by Board.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Bitboard(pub u64);
+
+impl Bitboard {
+    pub const EMPTY: Bitboard = Bitboard(0);
+
+    pub const fn contains(self, sq: Square) -> bool {
+        self.0 & 1 << sq.index() != 0
+    }
+
+    pub const fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// the lowest square
+    pub const fn first(self) -> Option<Square> {
+        match self.0 {
+            0 => None,
+            b => Some(Square::from_index(b.trailing_zeros() as u8)),
+        }
+    }
+
+    /// towards the 8th rank
+    pub const fn north(self) -> Bitboard {
+        Bitboard((self.0 & !ROW8) << 1)
+    }
+
+    /// towards the 1st rank
+    pub const fn south(self) -> Bitboard {
+        Bitboard((self.0 & !ROW1) >> 1)
+    }
+
+    /// towards the h-file
+    pub const fn east(self) -> Bitboard {
+        Bitboard(self.0 >> 8)
+    }
+
+    /// towards the a-file
+    pub const fn west(self) -> Bitboard {
+        Bitboard(self.0 << 8)
+    }
+}
+
+impl From<u64> for Bitboard {
+    fn from(bm: u64) -> Bitboard {
+        Bitboard(bm)
+    }
+}
+
+impl From<Bitboard> for u64 {
+    fn from(bb: Bitboard) -> u64 {
+        bb.0
+    }
+}
+
+impl FromIterator<Square> for Bitboard {
+    fn from_iter<I: IntoIterator<Item = Square>>(iter: I) -> Bitboard {
+        iter.into_iter()
+            .fold(Bitboard::EMPTY, |bb, sq| bb | sq.bb())
+    }
+}
+
+/// the squares of the set, lowest index first
+impl IntoIterator for Bitboard {
+    type Item = Square;
+    type IntoIter = Squares;
 
-    while b != 0 {
-        let i = b.trailing_zeros();
-        b &= !(1 << i);
-        out[n] = i as u8;
-        n += 1;
+    fn into_iter(self) -> Squares {
+        Squares(self)
     }
+}
+
+/// iterator over the squares of a Bitboard - see Bitboard::into_iter
+pub struct Squares(Bitboard);
+
+impl Iterator for Squares {
+    type Item = Square;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Square> {
+        let sq = self.0.first()?;
+        self.0.0 &= self.0.0 - 1;
+        Some(sq)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.0.count() as usize;
+        (n, Some(n))
+    }
+}
 
-    (out, n)
+impl ExactSizeIterator for Squares {}
+
+macro_rules! bitboard_ops {
+    ($($op:ident $f:ident $op_assign:ident $f_assign:ident),*) => {$(
+        impl $op for Bitboard {
+            type Output = Bitboard;
+            #[inline(always)]
+            fn $f(self, rhs: Bitboard) -> Bitboard {
+                Bitboard(self.0.$f(rhs.0))
+            }
+        }
+        impl $op_assign for Bitboard {
+            #[inline(always)]
+            fn $f_assign(&mut self, rhs: Bitboard) {
+                self.0.$f_assign(rhs.0)
+            }
+        }
+    )*};
+}
+bitboard_ops!(BitAnd bitand BitAndAssign bitand_assign,
+              BitOr bitor BitOrAssign bitor_assign,
+              BitXor bitxor BitXorAssign bitxor_assign);
+
+impl Not for Bitboard {
+    type Output = Bitboard;
+    fn not(self) -> Bitboard {
+        Bitboard(!self.0)
+    }
 }
 
-pub const fn bm_blockers(frm: u8, mut b: u64) -> u64 {
-    let mut bl = 0;
-    while b != 0 {
-        let i = b.trailing_zeros();
-        bl |= BM_BLOCKED[frm as usize][i as usize];
-        b &= !(1 << i);
+impl Shl<u32> for Bitboard {
+    type Output = Bitboard;
+    fn shl(self, n: u32) -> Bitboard {
+        Bitboard(self.0 << n)
+    }
+}
+
+impl Shr<u32> for Bitboard {
+    type Output = Bitboard;
+    fn shr(self, n: u32) -> Bitboard {
+        Bitboard(self.0 >> n)
+    }
+}
+
+/// 8th rank on top, like a diagram
+impl fmt::Display for Bitboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for rank in Rank::all().rev() {
+            write!(f, "{rank} ")?;
+            for file in File::all() {
+                let c = if self.contains(Square::new(file, rank)) {
+                    'x'
+                } else {
+                    '.'
+                };
+                write!(f, "{c}")?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "  ABCDEFGH")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_magics() {
+        // magic lookups agree with the rays for all blocker subsets
+        for frm in 0..64 {
+            for (directions, attacks) in [
+                (
+                    ROOK_DIRECTIONS,
+                    rook_attacks as fn(Square, Bitboard) -> Bitboard,
+                ),
+                (BISHOP_DIRECTIONS, bishop_attacks),
+            ] {
+                let mask = slider_mask(frm, &directions);
+                let mut occupied = 0u64;
+                loop {
+                    // squares outside the mask do not matter
+                    let board = occupied | !mask & 0x8142_2418_1824_4281;
+                    assert_eq!(
+                        attacks(Square::from_index(frm as u8), Bitboard(board)),
+                        Bitboard(slider_attacks(frm, board, &directions))
+                    );
+                    occupied = occupied.wrapping_sub(mask) & mask;
+                    if occupied == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+        let sq = |s: &str| Square::parse(s).unwrap();
+        let (a1, d4, h8, h1, c7, e4) = (sq("a1"), sq("d4"), sq("h8"), sq("h1"), sq("c7"), sq("e4"));
+        assert_eq!(
+            rook_attacks(h1, Bitboard::EMPTY),
+            Bitboard(BM_ROOK_MOVES[0])
+        );
+        assert_eq!(between(a1, h8).count(), 6);
+        assert_eq!(between(a1, h8), between(h8, a1));
+        assert_eq!(line(a1, d4), between(a1, h8) & !d4.bb() | h8.bb());
+        assert_eq!(between(a1, h1), line(a1, h1));
+        assert_eq!(between(a1, c7), Bitboard::EMPTY); // no line
+        assert_eq!(line(a1, c7), Bitboard::EMPTY);
+        assert_eq!(
+            queen_attacks(e4, Bitboard::EMPTY),
+            Bitboard(BM_QUEEN_MOVES[27])
+        );
+    }
+
+    #[test]
+    fn test_bitboard() {
+        let sq = |s: &str| Square::parse(s).unwrap();
+        let bb: Bitboard = ["e4", "a1", "h8"].iter().map(|s| sq(s)).collect();
+        assert_eq!(bb.count(), 3);
+        assert_eq!(bb.first(), Some(sq("h8")));
+        assert_eq!(
+            bb.into_iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            ["h8", "e4", "a1"]
+        );
+        assert_eq!(bb.into_iter().len(), 3);
+        assert_eq!(bb.count(), 3); // iterating does not consume the set
+        // shifts do not wrap around the edges
+        assert_eq!(bb.north(), sq("e5").bb() | sq("a2").bb());
+        assert_eq!(bb.south(), sq("e3").bb() | sq("h7").bb());
+        assert_eq!(bb.east(), sq("f4").bb() | sq("b1").bb());
+        assert_eq!(bb.west(), sq("d4").bb() | sq("g8").bb());
+        assert_eq!(Bitboard(ROW2) & File::A.bb(), sq("a2").bb());
+        assert_eq!(!Bitboard::EMPTY >> 63, sq("h1").bb());
+        assert_eq!(
+            sq("e4").bb().to_string(),
+            "8 ........\n7 ........\n6 ........\n5 ........\n\
+             4 ....x...\n3 ........\n2 ........\n1 ........\n  ABCDEFGH"
+        );
     }
-    bl
 }
@@ -794,3 +794,21 @@ pub const P2_HASH: [u64; 64] = [
 ];
 pub const NIL_HASH: [u64; 64] = [0; 64];
 pub const WHITE_HASH: u64 = 3832595165961311544;
+// castling rights: white short, long, black short, long
+pub const CASTLE_HASH: [u64; 4] = [
+    6066716377607654911,
+    3895408619969763354,
+    15781106517121928286,
+    9536438723124031678,
+];
+// en passant file: h..a
+pub const EN_PASSANT_HASH: [u64; 8] = [
+    5895580066249487202,
+    4175472891070665159,
+    784333586594559026,
+    3753943892682155721,
+    18408972560794325887,
+    11075945098630781902,
+    5541380772914522212,
+    9680957175178445257,
+];
@@ -1,15 +1,27 @@
 pub mod benchmark;
 pub mod bitmaps;
 pub mod hashkeys_generated;
+pub mod magics_generated;
 pub mod mgen;
 pub mod misc;
 pub mod openings;
+pub mod pawns;
+pub mod polyglot;
+pub mod polyglot_keys_generated;
+pub mod problems;
+pub mod square;
+pub mod strength;
+pub mod style;
+pub mod trace;
 pub mod transposition;
 pub mod val;
 
 use core::cmp::{max, min};
 use mgen::*;
+use pawns::PawnTable;
 use std::{fmt, time, time::Duration};
+use style::Style;
+use trace::{Cutoff, SearchTrace};
 use transposition::Transpositions;
 use val::*;
 
@@ -18,8 +30,26 @@ const INFINITE: i16 = 32000;
 pub struct Game {
     pub board: Board,
     pub n_searched: usize,
+    pub stats: SearchStats,
     pub ttable: Transpositions,
+    pub ptable: PawnTable,
+    pub trace: Option<SearchTrace>, // opt-in search tree trace of the last iteration
+    killers: Vec<[u16; 2]>,         // per ply - see MovePicker
     end_game: bool,
+    root_turn: Colour, // side to move at the root of the last search - see draw_score
+}
+
+/// Search counters - reset at the start of each search
+#[derive(Default, Debug, Clone)]
+pub struct SearchStats {
+    pub tt_probes: usize,
+    pub tt_hits: usize,
+    pub tt_cutoffs: usize,
+    pub beta_cutoffs: usize,
+    pub first_move_cutoffs: usize, // beta cut-off on the first move searched
+    pub q_nodes: usize,            // moves searched in quiescence
+    pub researches: usize,         // pvs re-searches after a null window fail high
+    pub extensions: usize,         // check & pawn push extensions
 }
 
 #[derive(Default)]
@@ -27,6 +57,49 @@ pub struct SearchInfo {
     pub depth: u8,
     pub nodes: usize,
     pub time: Duration,
+    pub stats: SearchStats,
+    pub ebf: Vec<f32>,   // effective branching factor per iteration
+    pub hashfull: usize, // permille of transposition table used
+}
+
+fn percent(n: usize, total: usize) -> f32 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * n as f32 / total as f32
+    }
+}
+
+impl fmt::Display for SearchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = &self.stats;
+        writeln!(
+            f,
+            "TT probes: {}, hits: {} ({:.1}%), cut-offs: {}, hashfull: {}",
+            s.tt_probes,
+            s.tt_hits,
+            percent(s.tt_hits, s.tt_probes),
+            s.tt_cutoffs,
+            self.hashfull
+        )?;
+        writeln!(
+            f,
+            "Beta cut-offs: {}, on first move: {} ({:.1}%)",
+            s.beta_cutoffs,
+            s.first_move_cutoffs,
+            percent(s.first_move_cutoffs, s.beta_cutoffs)
+        )?;
+        writeln!(
+            f,
+            "Quiescence nodes: {} ({:.1}%), re-searches: {}, extensions: {}",
+            s.q_nodes,
+            percent(s.q_nodes, self.nodes + s.q_nodes),
+            s.researches,
+            s.extensions
+        )?;
+        let ebf: Vec<String> = self.ebf.iter().map(|b| format!("{b:.1}")).collect();
+        write!(f, "EBF per iteration: [{}]", ebf.join(", "))
+    }
 }
 
 #[derive(Default, Clone)]
@@ -34,6 +107,7 @@ pub struct SearchConstraints {
     pub depth: Option<u8>,
     pub nodes: Option<usize>,
     pub time: Option<Duration>,
+    pub multipv: usize, // number of root moves scored exactly
 }
 
 impl SearchConstraints {
@@ -56,6 +130,11 @@ impl SearchConstraints {
         self.time = Some(Duration::from_millis(millis));
         self
     }
+
+    pub fn multipv(mut self, multipv: usize) -> Self {
+        self.multipv = multipv;
+        self
+    }
 }
 
 impl Default for Game {
@@ -79,15 +158,74 @@ impl fmt::Display for Game {
 
 impl Game {
     pub fn new(board: Board) -> Self {
+        Game::with_hash(board, transposition::DEFAULT_MB)
+    }
+
+    /// transposition table size in MB
+    pub fn with_hash(board: Board, mb: usize) -> Self {
         //println!("size of TEntry {}", std::mem::size_of::<TEntry>());
         Game {
+            root_turn: board.turn,
             board,
             n_searched: 0,
-            ttable: Transpositions::default(),
+            stats: SearchStats::default(),
+            ttable: Transpositions::new(mb),
+            ptable: PawnTable::default(),
+            trace: None,
+            killers: Vec::new(),
             end_game: false,
         }
     }
 
+    /// set up a new position - keeps the transposition table and style
+    pub fn set_position(&mut self, board: Board) {
+        let style = self.board.style;
+        self.board = board;
+        self.board.style = style;
+        self.n_searched = 0;
+        self.end_game = false;
+    }
+
+    /// evaluation and search bias
+    pub fn set_style(&mut self, style: Style) {
+        if self.board.style != style {
+            self.board.style = style;
+            self.ttable.clear(); // stored scores are style specific
+            self.ptable.clear();
+        }
+    }
+
+    /// static evaluation - pawn structure from the pawn table
+    fn eval(&mut self) -> i16 {
+        let pawns = self.ptable.get(&self.board);
+        self.board.eval_with_pawns(pawns.score)
+    }
+
+    /// draw score - with contempt the engine's side avoids draws
+    fn draw_score(&self) -> i16 {
+        let contempt = self.board.style.contempt;
+        if self.board.turn == self.root_turn {
+            -contempt
+        } else {
+            contempt
+        }
+    }
+
+    /// draw scores are stored without contempt - the root side changes between searches,
+    /// e.g. in self-play, and with it the sign of the contempt. See tt_score
+    fn tt_store(&mut self, depth: u8, score: i16, alpha: i16, beta: i16, m: Move) {
+        let draw = self.draw_score();
+        let offset = if score == draw { draw } else { 0 };
+        let (score, alpha, beta) = (score - offset, alpha - offset, beta - offset);
+        self.ttable
+            .store(self.board.hash, depth, score, alpha, beta, m);
+    }
+
+    /// stored score with the contempt of the current root - a stored 0 counts as a draw
+    fn tt_score(&self, score: i16) -> i16 {
+        if score == 0 { self.draw_score() } else { score }
+    }
+
     //https://cheatography.com/davechild/cheat-sheets/chess-algebraic-notation/
     pub fn move2label(&mut self, m: &Move, moves: &[Move]) -> String {
         fn i2xy(i: u8) -> (u8, u8) {
@@ -163,6 +301,24 @@ impl Game {
         label
     }
 
+    fn killers(&self, ply: usize) -> [u16; 2] {
+        self.killers.get(ply).copied().unwrap_or_default()
+    }
+
+    /// remember a quiet move that caused a beta cut-off - it is tried early at the same ply
+    fn store_killer(&mut self, ply: usize, m: &Move) {
+        if self.board[m.to() as usize] != EMPTY || self.board.is_en_passant(m) || m.is_promote() {
+            return;
+        }
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [0; 2]);
+        }
+        let k = &mut self.killers[ply];
+        if k[0] != m.data {
+            *k = [m.data, k[0]];
+        }
+    }
+
     fn is_quiescent(&self, last: &Move) -> bool {
         // quiescent unless last move was pawn near promotion
         // !self.in_check(self.colour) &&
@@ -187,67 +343,82 @@ impl Game {
         //adjust king value in end game
         self.end_game = self.board.is_end_game();
         self.board.full_move_count += 1;
+    }
 
-        //update castling permissions
-        match (self.board[m.to() as usize], m.frm()) {
-            (WKING, 24) => self.board.can_castle &= !CASTLE_W_SHORT & !CASTLE_W_LONG,
-            (BKING, 31) => self.board.can_castle &= !CASTLE_B_SHORT & !CASTLE_B_LONG,
-            (WROOK, 0) => self.board.can_castle &= !CASTLE_W_SHORT,
-            (WROOK, 56) => self.board.can_castle &= !CASTLE_W_LONG,
-            (BROOK, 7) => self.board.can_castle &= !CASTLE_B_SHORT,
-            (BROOK, 63) => self.board.can_castle &= !CASTLE_B_LONG,
-            _ => (),
+    fn quiescence_fab(&mut self, alpha: i16, beta: i16, last: &Move, rfab: bool) -> i16 {
+        let id = self
+            .trace
+            .as_mut()
+            .and_then(|t| t.enter(Some(*last), alpha, beta, true));
+        let (score, cutoff) = self.quiescence_node(alpha, beta, last, rfab);
+        if let Some(t) = self.trace.as_mut() {
+            t.leave(id, score, cutoff);
         }
+        score
     }
 
-    fn quiescence_fab(&mut self, alp: i16, beta: i16, last: &Move, rfab: bool) -> i16 {
-        let colour = self.board.turn;
-
+    fn quiescence_node(&mut self, alp: i16, beta: i16, last: &Move, rfab: bool) -> (i16, Cutoff) {
         let mut bscore = None;
         let mut alpha = alp;
-        let in_check = false; // TODO - calculate?
-        let mut moves = self.board.moves(in_check, self.end_game);
+        let mut moves = self.board.moves(false, self.end_game);
         if rfab {
             moves.retain(|m| self.board.is_en_passant(m) || m.to() == last.to())
         } else {
             moves.retain(|m| self.board.is_en_passant(m) || self.board[m.to() as usize] != EMPTY);
         }
+        self.board.retain_legal(&mut moves);
         if self.board.turn.is_white() {
-            moves.sort_unstable_by(|b, a| a.val.cmp(&b.val)); // decreasing
+            moves.sort_unstable_by_key(|m| std::cmp::Reverse(m.val)); // decreasing
         } else {
-            moves.sort_unstable_by(|a, b| a.val.cmp(&b.val)); // increasing
+            moves.sort_unstable_by_key(|m| m.val); // increasing
         }
         for m in moves {
+            self.stats.q_nodes += 1;
             self.board.update(&m);
-            if !self.board.in_check(colour) {
-                // legal move
-                let score = -self.quiescence_fab(-beta, -alpha, &m, true);
-                match bscore {
-                    Some(bs) if score <= bs => (),
-                    _ => {
-                        if score >= beta {
-                            self.board.backdate(&m);
-                            return score;
-                        }
-                        bscore = Some(score);
-                        alpha = max(alpha, score);
+            let score = -self.quiescence_fab(-beta, -alpha, &m, true);
+            self.board.backdate(&m);
+            match bscore {
+                Some(bs) if score <= bs => (),
+                _ => {
+                    if score >= beta {
+                        return (score, Cutoff::Beta);
                     }
+                    bscore = Some(score);
+                    alpha = max(alpha, score);
                 }
             }
-            self.board.backdate(&m);
         }
         if let Some(bs) = bscore {
-            bs
+            (bs, Cutoff::None)
         } else {
-            self.board.eval()
+            (self.eval(), Cutoff::Eval)
         }
     } // fn quiescence fab
 
     pub fn pvs(&mut self, depth: u8, ply: usize, alpha: i16, beta: i16, last: &Move) -> i16 {
+        let id = self
+            .trace
+            .as_mut()
+            .and_then(|t| t.enter(Some(*last), alpha, beta, false));
+        let (score, cutoff) = self.pvs_node(depth, ply, alpha, beta, last);
+        if let Some(t) = self.trace.as_mut() {
+            t.leave(id, score, cutoff);
+        }
+        score
+    }
+
+    fn pvs_node(
+        &mut self,
+        depth: u8,
+        ply: usize,
+        alpha: i16,
+        beta: i16,
+        last: &Move,
+    ) -> (i16, Cutoff) {
         if let Some(count) = self.board.rep.get(&self.board.hash)
             && *count >= 2
         {
-            return 0;
+            return (self.draw_score(), Cutoff::Repetition);
         }
 
         let mut alpha = alpha;
@@ -256,32 +427,46 @@ impl Game {
         let mut bmove = None;
         let colour = self.board.turn;
 
+        self.stats.tt_probes += 1;
         let kmove = if let Some(e) = self.ttable.probe(self.board.hash) {
+            self.stats.tt_hits += 1;
             if e.depth() >= depth {
+                let score = self.tt_score(e.score());
                 if e.exact_bound() {
-                    return e.score();
+                    self.stats.tt_cutoffs += 1;
+                    return (score, Cutoff::TTable);
                 } else if e.lower_bound() {
-                    alpha = max(alpha, e.score())
+                    alpha = max(alpha, score)
                 } else {
-                    beta = min(beta, e.score())
+                    beta = min(beta, score)
                 }
                 if alpha >= beta {
-                    return e.score();
+                    self.stats.tt_cutoffs += 1;
+                    return (score, Cutoff::TTable);
                 }
             }
-            let (frm, to) = e.frmto();
-            Some(self.board.infer_move(frm, to))
+            self.board
+                .move_from_encoding(e.move_data())
+                .filter(|m| self.board.is_legal(m))
         } else {
             None
         };
 
         let in_check = self.board.in_check(colour);
         let depth = match (depth, in_check) {
-            (_, true) => depth + 1,
+            (_, true) => {
+                self.stats.extensions += 1;
+                depth + 1
+            }
             (0, false) if self.is_quiescent(last) => {
-                return self.quiescence_fab(alpha, beta, last, false);
+                // quiescence nodes are traced as children of this node
+                let (score, _) = self.quiescence_node(alpha, beta, last, false);
+                return (score, Cutoff::Quiescence);
+            }
+            (0, false) => {
+                self.stats.extensions += 1;
+                1
             }
-            (0, false) => 1,
             (_, false) => depth,
         };
 
@@ -301,68 +486,54 @@ impl Game {
         //     }
         // }
 
-        let mut moves = Vec::new();
-        if let Some(k) = kmove {
-            moves.push(k);
-        }
-        let mut generated = false;
-        loop {
-            let m = match moves.pop() {
-                Some(m) => m,
-                None if !generated => {
-                    generated = true;
-                    moves = self.board.moves(in_check, self.end_game);
-                    if let Some(k) = kmove
-                        && let Some(q) = moves.iter().position(|&m| m == k)
-                    {
-                        moves.remove(q);
-                    }
-                    if self.board.turn.is_white() {
-                        moves.sort_unstable_by(|a, b| a.val.cmp(&b.val)); // increasing
-                    } else {
-                        moves.sort_unstable_by(|b, a| a.val.cmp(&b.val)); // decreasing
-                    }
-                    continue;
-                }
-                None => break,
-            };
+        let mut picker = MovePicker::new(&self.board, kmove, self.killers(ply), self.end_game);
+        let mut n_legal = 0;
+        while let Some(m) = picker.next(&self.board) {
             self.n_searched += 1;
             self.board.update(&m);
-            if !self.board.in_check(colour) {
-                // legal move
-                if bmove.is_none() {
-                    bscore = -self.pvs(depth - 1, ply + 1, -beta, -alpha, &m); // full beam
-                    bmove = Some(m);
-                } else {
-                    let mut score = -self.pvs(
-                        depth - 1,
-                        ply + 1,
-                        -max(alpha, bscore) - 1,
-                        -max(alpha, bscore),
-                        &m,
-                    );
-                    if score > bscore {
-                        if score > max(bscore, alpha) && score < beta && depth > 2 {
-                            score = -self.pvs(depth - 1, ply + 1, -beta, -score, &m);
-                        }
-                        bscore = score;
-                        bmove = Some(m);
+            n_legal += 1;
+            if bmove.is_none() {
+                bscore = -self.pvs(depth - 1, ply + 1, -beta, -alpha, &m); // full beam
+                bmove = Some(m);
+            } else {
+                let mut score = -self.pvs(
+                    depth - 1,
+                    ply + 1,
+                    -max(alpha, bscore) - 1,
+                    -max(alpha, bscore),
+                    &m,
+                );
+                if score > bscore {
+                    if score > max(bscore, alpha) && score < beta && depth > 2 {
+                        self.stats.researches += 1;
+                        score = -self.pvs(depth - 1, ply + 1, -beta, -score, &m);
                     }
+                    bscore = score;
+                    bmove = Some(m);
                 }
             }
             self.board.backdate(&m);
             if bscore >= beta {
+                self.stats.beta_cutoffs += 1;
+                if n_legal == 1 {
+                    self.stats.first_move_cutoffs += 1;
+                }
+                self.store_killer(ply, &m);
                 break;
             }
         }
 
         match (bmove, in_check) {
-            (None, false) => 0,
-            (None, true) => bscore,
+            (None, false) => (self.draw_score(), Cutoff::Stalemate),
+            (None, true) => (bscore, Cutoff::Checkmate),
             (Some(m), _) => {
-                self.ttable
-                    .store(self.board.hash, depth, bscore, alpha, beta, m);
-                bscore
+                self.tt_store(depth, bscore, alpha, beta, m);
+                let cutoff = if bscore >= beta {
+                    Cutoff::Beta
+                } else {
+                    Cutoff::None
+                };
+                (bscore, cutoff)
             }
         }
     }
@@ -374,7 +545,7 @@ impl Game {
         verbose: bool,
     ) -> (Vec<(Move, i16)>, SearchInfo) {
         // top level pvs - does iterative deepening, sorts moves
-        // note that only the best move has exact scoring...
+        // note that only the best move has exact scoring - or the sc.multipv best...
 
         let mut search_info = SearchInfo::default();
         let start = time::Instant::now();
@@ -384,9 +555,14 @@ impl Game {
         }
 
         self.n_searched = 0;
+        self.stats = SearchStats::default();
+        self.killers.clear();
+        self.root_turn = self.board.turn;
+        self.ttable.new_search();
         let mut pq0: Vec<(Move, i16)> = moves.iter().map(|m| (*m, 0)).collect();
 
         let mut last_depth_time = Duration::from_millis(0);
+        let mut last_depth_nodes = 0;
 
         if let Some(time_limit) = sc.time {
             println!("info time_limit={time_limit:?}");
@@ -400,22 +576,31 @@ impl Game {
             }
 
             let depth_start = time::Instant::now();
+            let depth_nodes_start = self.n_searched;
             let mut pq: Vec<(Move, i16)> = Vec::new();
             let mut alpha = -INFINITE;
             let beta = INFINITE;
             let mut bscore = alpha;
+            let root = self.trace.as_mut().and_then(|t| {
+                t.clear(); // keep the tree of the last iteration only
+                t.enter(None, alpha, beta, false)
+            });
 
             for (i, (m, _v)) in pq0.iter().enumerate() {
                 self.board.update(m);
                 alpha = max(bscore, alpha);
+                let multipv = i > 0 && i < sc.multipv;
                 let mut score = if i == 0 {
                     -self.pvs(depth - 1, 1, -beta, -alpha, m) // full beam
+                } else if multipv {
+                    -self.pvs(depth - 1, 1, -beta, INFINITE, m) // exact score
                 } else {
                     -self.pvs(depth - 1, 1, -alpha - 1, -alpha, m)
                 };
 
                 if score > bscore {
-                    if score > alpha && score < beta && depth > 2 {
+                    if !multipv && score > alpha && score < beta && depth > 2 {
+                        self.stats.researches += 1;
                         score = -self.pvs(depth - 1, 1, -beta, -score, m);
                     }
                     bscore = score;
@@ -423,8 +608,11 @@ impl Game {
                 self.board.backdate(m);
                 pq.push((*m, score));
             }
-            pq.sort_by(|b, a| a.1.cmp(&b.1)); // decreasing
+            pq.sort_by_key(|a| std::cmp::Reverse(a.1)); // decreasing
             pq0 = pq;
+            if let Some(t) = self.trace.as_mut() {
+                t.leave(root, bscore, Cutoff::None);
+            }
             if verbose {
                 println!(
                     "info Depth {depth:>2} #searched {:>8} bmove: {} bscore: {bscore}",
@@ -435,6 +623,15 @@ impl Game {
             search_info.nodes = self.n_searched;
             search_info.depth = depth;
             search_info.time = elapsed;
+            search_info.stats = self.stats.clone();
+            search_info.hashfull = self.ttable.hashfull();
+            let depth_nodes = self.n_searched - depth_nodes_start;
+            if last_depth_nodes > 0 {
+                search_info
+                    .ebf
+                    .push(depth_nodes as f32 / last_depth_nodes as f32);
+            }
+            last_depth_nodes = depth_nodes;
             if (sc.nodes.is_some() && self.n_searched >= sc.nodes.unwrap())
                 || (sc.depth.is_some() && depth >= sc.depth.unwrap())
                 || (sc.time.is_some() && elapsed >= sc.time.unwrap())
@@ -460,4 +657,55 @@ mod tests {
             "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
         )
     }
+
+    #[test]
+    fn test_search_stats() {
+        let mut game = Game::new(Board::default());
+        let moves = game.board.legal_moves();
+        let sc = SearchConstraints::default().depth(4);
+        let (_, info) = game.score_moves(&moves, &sc, false);
+        let s = &info.stats;
+        assert!(s.tt_hits <= s.tt_probes && s.tt_cutoffs <= s.tt_hits);
+        assert!(s.first_move_cutoffs <= s.beta_cutoffs && s.beta_cutoffs > 0);
+        assert_eq!(info.ebf.len(), info.depth as usize - 2);
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut game = Game::new(Board::default());
+        game.trace = Some(trace::SearchTrace::new(2, 100));
+        let moves = game.board.legal_moves();
+        let sc = SearchConstraints::default().depth(3);
+        game.score_moves(&moves, &sc, false);
+        let t = game.trace.as_ref().unwrap();
+        assert_eq!(t.nodes.len(), 100);
+        assert!(t.nodes[0].parent.is_none() && t.nodes[0].mv.is_none());
+        assert!(t.nodes[1..].iter().all(|n| {
+            let p = &t.nodes[n.parent.unwrap()];
+            n.ply == p.ply + 1 && n.ply <= 2
+        }));
+        assert!(t.to_dot().contains("n0 -> n1;"));
+        assert!(t.to_json().starts_with("[\n  {\"id\": 0, \"parent\": null"));
+    }
+
+    #[test]
+    fn test_contempt_root_turn() {
+        // draw scores are stored without contempt - the table is kept when the root side changes
+        let mut game = Game::new(Board::default());
+        game.set_style(style::AGGRESSIVE);
+        let contempt = game.board.style.contempt;
+        let sc = SearchConstraints::default().depth(2);
+        let m = game.board.legal_moves()[0];
+        game.tt_store(1, -contempt, -INFINITE, INFINITE, m);
+        let hash = game.board.hash;
+        assert_eq!(game.ttable.probe(hash).unwrap().score(), 0);
+        assert_eq!(game.tt_score(0), -contempt);
+
+        game.set_position(Board::from_fen("4k3/8/8/8/8/8/8/4K2R b K - 0 1").unwrap());
+        game.score_moves(&game.board.legal_moves(), &sc, false);
+        assert!(game.ttable.probe(hash).is_some());
+        // white to move is now the opponent of the root - a draw is welcome
+        game.set_position(Board::default());
+        assert_eq!(game.tt_score(0), contempt);
+    }
 }
@@ -0,0 +1,134 @@
+// Magic numbers generated by bin/build_magics.rs
+
+pub const ROOK_MAGICS: [u64; 64] = [
+    900720023729946624,
+    666541816090206208,
+    612524735843009288,
+    612498351867297796,
+    9583677908484883012,
+    4683752425738338560,
+    324260272715866240,
+    9295436505004638378,
+    288371114713841720,
+    4612037862686195776,
+    141287512612864,
+    2392571662828672,
+    2594214672743793664,
+    289497564410020864,
+    4693876781453967624,
+    1407377303798016,
+    35734136307712,
+    18296974071570560,
+    14270853887535743104,
+    2393087192076289,
+    4758053556215350272,
+    3472416599980377088,
+    145245488210772240,
+    9800116463174959236,
+    70379483987968,
+    576603697407082752,
+    432363158569492480,
+    86711889309993217,
+    360292370392025088,
+    1152991907729638416,
+    2305851959929815600,
+    1301576052208109636,
+    36064256277348488,
+    153157640426356810,
+    18031992985624576,
+    5767140866560886816,
+    73464986386565121,
+    4400202383872,
+    594774252402508289,
+    9512165985897939012,
+    141838107410464,
+    4796333740593922048,
+    9227946151263731728,
+    36037661848305696,
+    9516677762976382980,
+    4773820003092824192,
+    4629726876368371778,
+    39875022290961,
+    1189021770762420992,
+    1284916788595840,
+    612524735843008640,
+    2306124552910414080,
+    140771848355968,
+    3459891513272582208,
+    2378182086818529536,
+    9223389942774792704,
+    4755818937218793473,
+    5766384480004636738,
+    1153484489465282578,
+    4512739322274049,
+    288793395362006018,
+    2533446891339953,
+    3612449855533777922,
+    11720618597721328642,
+];
+pub const BISHOP_MAGICS: [u64; 64] = [
+    2308139068799592704,
+    74311047447707968,
+    1157728586644332544,
+    20301675827834882,
+    3417420115476512,
+    143074487567872,
+    74835578389256,
+    9223653658128943152,
+    17730178916512,
+    22058989912236,
+    290822989941250,
+    6926897005294264576,
+    9572357089796096,
+    3304458289408,
+    1765412256537938182,
+    2341942338287305730,
+    2287122733072896,
+    3459890422921565184,
+    1193467151831302160,
+    1739551124593123330,
+    10093697082228473860,
+    90353493344585984,
+    2305983819787870217,
+    9297998254455394324,
+    38355673014077492,
+    180460786311906305,
+    564066779612168,
+    11543856142908195328,
+    360569513902620672,
+    22519107924557962,
+    11529358583910764608,
+    289356623959425152,
+    1159679313545532420,
+    72203073881113106,
+    19140470215311872,
+    11534072690600575104,
+    4614511771900969032,
+    77124701964535808,
+    182396888987992192,
+    16048472390140424,
+    577587786215932070,
+    3378267797196810,
+    1189038917672767492,
+    2309224282619740672,
+    576777429104724996,
+    5476975557319065856,
+    2891312064595755520,
+    4765415337267052674,
+    576539926000042112,
+    576517999990669316,
+    4899918602209370122,
+    306244775209074752,
+    17627401687056,
+    1152925937148904448,
+    5068852237172740,
+    657534625698038784,
+    598692807574016,
+    1152921644260663296,
+    1158565574884755460,
+    579838494982276096,
+    70369283155080,
+    167482295375364353,
+    149572506651137,
+    7925316337272068,
+];
@@ -1,21 +1,61 @@
 use crate::bitmaps::*;
-use crate::hashkeys_generated::WHITE_HASH;
-use crate::misc::{self, sq2i};
+use crate::hashkeys_generated::{CASTLE_HASH, EN_PASSANT_HASH, WHITE_HASH};
+use crate::misc::sq2i;
+use crate::square::{File, Rank, Square};
+use crate::style::Style;
 use crate::val::*;
 use crate::val::{BLACK, BPAWN, Colour, Piece, WHITE, WPAWN};
 use std::collections::hash_map::{Entry, HashMap};
 use std::fmt;
-use std::ops::{Index, IndexMut};
+use std::ops::{Deref, DerefMut, Index, IndexMut};
 use std::slice::Iter;
 
-#[derive(Debug, Copy, Clone)]
+// occupied squares by colour and by kind of piece - kinds indexed by Piece::index()
+#[derive(Debug, Copy, Clone, PartialEq)]
 struct Bitmaps {
     pieces: [u64; 2],
-    pawns: u64,
-    kings: u64,
+    kinds: [u64; 6],
+}
+
+impl Bitmaps {
+    /// put p on sq, or take it off again - nothing for an empty square
+    #[inline(always)]
+    const fn toggle(&mut self, p: Piece, sq: u8) {
+        if p.kind() != 0 {
+            self.pieces[p.colour().as_usize()] ^= 1 << sq;
+            self.kinds[p.index()] ^= 1 << sq;
+        }
+    }
+
+    /// squares holding one of the kinds of pieces, of either colour
+    #[inline(always)]
+    const fn of_kind(&self, kinds: u8) -> u64 {
+        let mut bm = 0;
+        let mut k = kinds & (ROOK | KNIGHT | BISHOP | QUEEN | KING | PAWN);
+        while k != 0 {
+            bm |= self.kinds[(k.trailing_zeros() - 1) as usize];
+            k &= k - 1;
+        }
+        bm
+    }
+
+    /// squares holding the kind of piece of colour
+    #[inline(always)]
+    const fn of(&self, kind: u8, colour: Colour) -> u64 {
+        self.of_kind(kind) & self.pieces[colour.as_usize()]
+    }
+}
+
+// checks and pins against the king of the side to move - see Board::generate_legal
+struct Checks {
+    king: Square,
+    checkers: Bitboard,
+    pinned: Bitboard,   // own pieces shielding the king from an enemy slider
+    evasions: Bitboard, // targets of the other pieces - capture or block a single checker
 }
 
-// bitpacking - 15 bits: 1st 12 (6+6) for from/to, next 3 bits for pawn promotion
+// bitpacking - 16 bits: 1st 12 (6+6) for from/to, next 3 bits for pawn promotion,
+// last bit for castling
 const TO_SHIFT: u16 = 6;
 pub const CASTLE_W_SHORT: u8 = 0b0001;
 pub const CASTLE_W_LONG: u8 = 0b0010;
@@ -28,6 +68,14 @@ pub const PROMOTE_KNIGHT: u16 = 0b0100000_00000000;
 pub const PROMOTE_BISHOP: u16 = 0b0110000_00000000;
 pub const PROMOTE_QUEEN: u16 = 0b1000000_00000000;
 pub const PROMOTE_MASK: u16 = 0b1110000_00000000;
+pub const CASTLE: u16 = 0b10000000_00000000; // to is the king's square after castling
+
+// castling - white short, long, black short, long, same order as the can_castle bits.
+// King and rook end up on the same squares wherever they start (Chess960)
+const CASTLE_KING_TO: [u8; 4] = [sq2i("g1"), sq2i("c1"), sq2i("g8"), sq2i("c8")];
+const CASTLE_ROOK_TO: [u8; 4] = [sq2i("f1"), sq2i("d1"), sq2i("f8"), sq2i("d8")];
+const STANDARD_ROOKS: [u8; 4] = [sq2i("h1"), sq2i("a1"), sq2i("h8"), sq2i("a8")];
+const STANDARD_KINGS: [u8; 4] = [sq2i("e1"), sq2i("e1"), sq2i("e8"), sq2i("e8")];
 
 #[inline(always)]
 const fn pack_data(promote: u16, frm: u8, to: u8) -> u16 {
@@ -77,6 +125,14 @@ impl Move {
         ((self.data & TO_MASK) >> TO_SHIFT) as u8
     }
 
+    pub fn frm_sq(&self) -> Square {
+        Square::from_index(self.frm())
+    }
+
+    pub fn to_sq(&self) -> Square {
+        Square::from_index(self.to())
+    }
+
     /// frm,to - 12 lower bits (6+6)
     #[inline]
     pub fn encode(&self) -> u16 {
@@ -94,10 +150,77 @@ pub fn ext_to(data: u16) -> u8 {
     ((data & TO_MASK) >> TO_SHIFT) as u8
 }
 
+pub const MAX_MOVES: usize = 256;
+
+/// fixed capacity move list - lives on the stack, so generating moves does not allocate
+#[derive(Clone, Copy)]
+pub struct MoveList {
+    moves: [Move; MAX_MOVES],
+    len: usize,
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        MoveList {
+            moves: [Move { data: 0, val: 0 }; MAX_MOVES],
+            len: 0,
+        }
+    }
+}
+
+impl MoveList {
+    #[inline(always)]
+    pub fn push(&mut self, m: Move) {
+        self.moves[self.len] = m;
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&Move) -> bool) {
+        let mut n = 0;
+        for i in 0..self.len {
+            if keep(&self.moves[i]) {
+                self.moves[n] = self.moves[i];
+                n += 1;
+            }
+        }
+        self.len = n;
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+    fn deref(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [Move] {
+        &mut self.moves[..self.len]
+    }
+}
+
+// which moves to generate - staged generation splits captures from quiet moves
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Gen {
+    All,
+    Captures, // captures, en passant & promotions
+    Quiets,   // the rest, castling included
+}
+
 impl fmt::Display for Move {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let (frm, to) = (self.frm() as usize, self.to() as usize);
-        write!(f, "{}{}{}", I2SQ[frm], I2SQ[to], self.promote_label())
+        write!(
+            f,
+            "{}{}{}",
+            self.frm_sq(),
+            self.to_sq(),
+            self.promote_label()
+        )
     }
 }
 
@@ -107,13 +230,18 @@ pub struct Board {
     pub can_castle: u8, // white short, long, black short, long
     pub material: i16,
     pub hash: u64,
+    pub pawn_hash: u64,         // pawns only - key for the pawn structure table
     pub half_move_clock: usize, // since last irreversible move
     pub full_move_count: usize,
     pub rep: HashMap<u64, u8>,
+    pub style: Style,
+    pub chess960: bool, // castling is written as king takes rook in UCI notation
     en_passant_sq: u8,
+    castle_rooks: [u8; 4], // start squares of the castling rooks - see CASTLE_KING_TO
+    castle_masks: [u8; 64], // castling rights lost by moving from or to a square
     bitmaps: Bitmaps,
     end_game_material: i16,
-    log_bms: Vec<(Bitmaps, Piece, u64, u8, u8)>,
+    log_bms: Vec<(Bitmaps, Piece, u64, u64, u8, u8)>,
 }
 
 impl Default for Board {
@@ -137,6 +265,21 @@ impl IndexMut<usize> for Board {
     }
 }
 
+impl Index<Square> for Board {
+    type Output = Piece;
+    #[inline(always)]
+    fn index(&self, sq: Square) -> &Self::Output {
+        &self.squares[sq.as_usize()]
+    }
+}
+
+impl IndexMut<Square> for Board {
+    #[inline(always)]
+    fn index_mut(&mut self, sq: Square) -> &mut Self::Output {
+        &mut self.squares[sq.as_usize()]
+    }
+}
+
 impl<'a> IntoIterator for &'a Board {
     type Item = &'a Piece;
     type IntoIter = Iter<'a, Piece>;
@@ -149,10 +292,10 @@ impl<'a> IntoIterator for &'a Board {
 impl fmt::Debug for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f)?;
-        for y in (0..8).rev() {
-            write!(f, "{} ", y + 1)?;
-            for x in 0..8 {
-                write!(f, "{}", self.squares[(7 - x) * 8 + y])?;
+        for rank in Rank::all().rev() {
+            write!(f, "{rank} ")?;
+            for file in File::all() {
+                write!(f, "{}", self[Square::new(file, rank)])?;
             }
             writeln!(f)?;
         }
@@ -171,17 +314,13 @@ impl fmt::Display for Board {
         let white_fg = "\x1b[38;5;15m"; // White foreground
         let reset_colour = "\x1b[0m"; // Reset to default colour
 
-        for y in (0..8).rev() {
-            write!(f, "{} ", y + 1)?;
-            for x in 0..8 {
-                let i = (7 - x) * 8 + y;
-                let ch = self.squares[i].to_unicode();
-                let fg = if self.squares[i].is_white() {
-                    white_fg
-                } else {
-                    black_fg
-                };
-                let is_light_square = (x + y) % 2 != 0;
+        for rank in Rank::all().rev() {
+            write!(f, "{rank} ")?;
+            for file in File::all() {
+                let p = self[Square::new(file, rank)];
+                let ch = p.to_unicode();
+                let fg = if p.is_white() { white_fg } else { black_fg };
+                let is_light_square = (file.index() + rank.index()) % 2 != 0;
                 let background_color = if is_light_square {
                     light_square_bg
                 } else {
@@ -201,94 +340,231 @@ impl fmt::Display for Board {
 }
 
 impl Board {
-    /// Fills in value - assumes frm,to is a valid board move & that promotion is to queen...
-    pub fn infer_move(&self, frm: u8, to: u8) -> Move {
-        match self.squares[frm as usize].kind() {
-            ROOK | KNIGHT | BISHOP | QUEEN => Move {
-                data: pack_data(0, frm, to),
-                val: self.delta_val(frm, to),
-            },
-            KING => match (frm, to) {
-                // W_SHORT, W_LONG B_SHORT B_LONG
-                (24, 8) => Move {
-                    data: pack_data(0, frm, to),
-                    val: self.delta_val(frm, to) + self.delta_val(sq2i("h8"), sq2i("f8")),
-                },
-                (24, 40) => Move {
-                    data: pack_data(0, frm, to),
-                    val: self.delta_val(frm, to) + self.delta_val(sq2i("a8"), sq2i("d8")),
-                },
-                (31, 15) => Move {
-                    data: pack_data(0, frm, to),
-                    val: self.delta_val(frm, to) + self.delta_val(sq2i("h1"), sq2i("f1")),
-                },
-                (31, 47) => Move {
-                    data: pack_data(0, frm, to),
-                    val: self.delta_val(frm, to) + self.delta_val(sq2i("a1"), sq2i("d1")),
-                },
-                _ => Move {
-                    data: pack_data(0, frm, to),
-                    val: self.delta_val(frm, to),
-                },
-            },
-            PAWN => match to % 8 {
-                0 | 7 => Move {
-                    data: pack_data(PROMOTE_QUEEN, frm, to),
-                    val: Piece::new(QUEEN, self.turn).val(to)
-                        - self[frm as usize].val(frm)
-                        - self[to as usize].val(to),
-                },
-                2 | 5 if self.en_passant_sq == to => {
-                    //lto - the sq the captured pawn is actually on
-                    let lto = self.en_passant_sq + 2 * self.turn.opposite().as_u8() - 1;
-                    Move {
-                        data: pack_data(0, frm, to),
-                        val: self[frm as usize].val(to)
-                            - self[frm as usize].val(frm)
-                            - self[lto as usize].val(lto),
-                    }
-                }
-                _ => Move {
-                    data: pack_data(0, frm, to),
-                    val: self.delta_val(frm, to),
-                },
-            },
-            _ => unimplemented!(),
+    /// Decode a move, e.g. from the transposition table. None unless the move is
+    /// pseudo-legal in this position - a hash collision can not produce an illegal move.
+    pub fn move_from_encoding(&self, data: u16) -> Option<Move> {
+        let frm = ext_frm(data);
+        if 1 << frm & self.bitmaps.pieces[self.turn.as_usize()] == 0 {
+            return None;
         }
+        let (end_game, in_check) = match self.squares[frm as usize].kind() {
+            KING => (self.is_end_game(), self.in_check(self.turn)),
+            _ => (false, false),
+        };
+        let mut v = MoveList::default();
+        self.piece_moves(&mut v, frm, end_game, in_check, Gen::All);
+        v.iter().find(|m| m.data == data).copied()
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        self.generate_legal(false)
+    }
+
+    /// legal moves - like moves(), but checks and pins are computed once,
+    /// instead of making every move to see if it exposes the king
+    pub fn generate_legal(&self, end_game: bool) -> Vec<Move> {
+        let mut v = MoveList::default();
+        self.generate(&mut v, Gen::All, end_game, &self.checks());
+        v.to_vec()
+    }
+
+    /// legal moves of the kind added to v
+    fn generate(&self, v: &mut MoveList, wanted: Gen, end_game: bool, checks: &Option<Checks>) {
+        let own = self.bitmaps.pieces[self.turn.as_usize()];
+        let Some(checks) = checks else {
+            // no king
+            bits(own).for_each(|frm| self.piece_moves(v, frm as u8, end_game, false, wanted));
+            return;
+        };
+        let in_check = !checks.checkers.is_empty();
+        if checks.checkers.count() > 1 {
+            // double check - only the king can move
+            self.piece_moves(v, checks.king.index(), end_game, in_check, wanted);
+        } else {
+            bits(own).for_each(|frm| self.piece_moves(v, frm as u8, end_game, in_check, wanted));
+        }
+        v.retain(|m| self.is_legal_with(m, checks));
+    }
+
+    /// keep the legal moves of a pseudo-legal move list
+    pub fn retain_legal(&self, moves: &mut Vec<Move>) {
+        if let Some(checks) = self.checks() {
+            moves.retain(|m| self.is_legal_with(m, &checks));
+        }
+    }
+
+    /// true if the pseudo-legal move does not leave the own king in check
+    pub fn is_legal(&self, m: &Move) -> bool {
+        match self.checks() {
+            Some(checks) => self.is_legal_with(m, &checks),
+            None => true,
+        }
+    }
+
+    fn is_legal_with(&self, m: &Move, checks: &Checks) -> bool {
+        let (frm, to) = (m.frm_sq(), m.to_sq());
+        let occupied = Bitboard(self.bitmaps.pieces[0] | self.bitmaps.pieces[1]);
+        let opponent = self.turn.opposite();
+        if self.is_castle(m) {
+            true // verified by king_moves
+        } else if frm == checks.king {
+            self.attackers(to, occupied ^ frm.bb(), opponent).is_empty()
+        } else if self.is_en_passant(m) {
+            // two pawns leave the rank of the king - test the position after the capture
+            let captured = Square::from_index(to.index() + 2 * opponent.as_usize() as u8 - 1);
+            let occupied = occupied ^ frm.bb() ^ captured.bb() | to.bb();
+            (self.attackers(checks.king, occupied, opponent) & !captured.bb()).is_empty()
+        } else {
+            checks.evasions.contains(to)
+                && (!checks.pinned.contains(frm) || line(checks.king, frm).contains(to))
+        }
+    }
+
+    /// most valuable victim first, then least valuable attacker
+    fn capture_score(&self, m: &Move) -> i32 {
+        let victim = if self.is_en_passant(m) {
+            PAWN
+        } else {
+            self[m.to() as usize].kind()
+        };
+        let attacker = self[m.frm() as usize].kind();
+        16 * (exchange_value(victim) + exchange_value(m.promote_kind())) - exchange_value(attacker)
+    }
+
+    /// captures that do not lose material - the victim is worth as much as the
+    /// attacker, or it is not defended; promotions are always good
+    fn is_good_capture(&self, m: &Move) -> bool {
+        let victim = self[m.to() as usize].kind();
+        let attacker = self[m.frm() as usize].kind();
+        m.is_promote()
+            || self.is_en_passant(m)
+            || exchange_value(victim) >= exchange_value(attacker)
+            || self
+                .attackers_to(m.to_sq(), self.turn.opposite())
+                .is_empty()
+    }
+
+    /// checkers, pinned pieces and evasion squares of the side to move
+    fn checks(&self) -> Option<Checks> {
+        let own = Bitboard(self.bitmaps.pieces[self.turn.as_usize()]);
+        let opp = Bitboard(self.bitmaps.pieces[self.turn.opposite().as_usize()]);
+        let king = Bitboard(self.bitmaps.of(KING, self.turn)).first()?;
+        let occupied = own | opp;
+        let checkers = self.attackers(king, occupied, self.turn.opposite());
+
+        // enemy sliders that would attack the king if own pieces were removed
+        let of_kind = |kinds| Bitboard(self.bitmaps.of_kind(kinds));
+        let snipers = rook_attacks(king, opp) & opp & of_kind(ROOK | QUEEN)
+            | bishop_attacks(king, opp) & opp & of_kind(BISHOP | QUEEN);
+        let pinned = snipers
+            .into_iter()
+            .map(|sq| between(king, sq) & occupied)
+            .filter(|b| b.count() == 1)
+            .fold(Bitboard::EMPTY, |pinned, b| pinned | b & own);
+
+        let evasions = match checkers.first() {
+            None => !Bitboard::EMPTY,
+            Some(checker) if checkers.count() == 1 => checkers | between(king, checker),
+            Some(_) => Bitboard::EMPTY,
+        };
+        Some(Checks {
+            king,
+            checkers,
+            pinned,
+            evasions,
+        })
     }
 
-    fn legal_move(&mut self, m: &Move) -> bool {
-        // verify move does not expose own king
-        self.update(m);
-        let flag = self.in_check(self.turn.opposite());
-        self.backdate(m);
-        !flag
+    /// attackers_to, given the occupied squares
+    fn attackers(&self, sq: Square, occupied: Bitboard, colour: Colour) -> Bitboard {
+        let (i, bm) = (sq.as_usize(), self.bitmaps.pieces[colour.as_usize()]);
+        Bitboard(
+            BM_PAWN_CAPTURES[colour.opposite().as_usize()][i] & bm & self.bitmaps.of_kind(PAWN)
+                | BM_KING_MOVES[i] & bm & self.bitmaps.of_kind(KING)
+                | BM_KNIGHT_MOVES[i] & bm & self.bitmaps.of_kind(KNIGHT)
+                | rook_attacks(sq, occupied).0 & bm & self.bitmaps.of_kind(ROOK | QUEEN)
+                | bishop_attacks(sq, occupied).0 & bm & self.bitmaps.of_kind(BISHOP | QUEEN),
+        )
     }
 
-    pub fn legal_moves(&mut self) -> Vec<Move> {
-        let in_check = self.in_check(self.turn);
-        let mut moves = self.moves(in_check, false);
-        moves.retain(|m| self.legal_move(m));
-        moves
+    /// number of leaf nodes in the legal move tree of the given depth -
+    /// the last ply is counted in bulk
+    pub fn perft(&mut self, depth: usize) -> usize {
+        match depth {
+            0 => 1,
+            1 => self.legal_moves().len(),
+            _ => self.divide(depth).iter().map(|(_, n)| n).sum(),
+        }
+    }
+
+    /// perft split on the legal moves of the position
+    pub fn divide(&mut self, depth: usize) -> Vec<(Move, usize)> {
+        self.legal_moves()
+            .into_iter()
+            .map(|m| {
+                self.update(&m);
+                let n = self.perft(depth.saturating_sub(1));
+                self.backdate(&m);
+                (m, n)
+            })
+            .collect()
     }
 
     /// true if move is castle right or left
     #[inline(always)]
     pub fn is_castle(&self, m: &Move) -> bool {
-        match (m.frm(), m.to(), self.turn.is_white()) {
-            (24, 8, true) => self.can_castle & CASTLE_W_SHORT != 0,
-            (24, 40, true) => self.can_castle & CASTLE_W_LONG != 0,
-            (31, 15, false) => self.can_castle & CASTLE_B_SHORT != 0,
-            (31, 47, false) => self.can_castle & CASTLE_B_LONG != 0,
-            _ => false,
+        m.data & CASTLE != 0
+    }
+
+    /// start and end squares of the rook in a castling move
+    pub fn castle_rook(&self, m: &Move) -> Option<(u8, u8)> {
+        self.is_castle(m).then(|| {
+            // the king goes to the g-file (8-15) or the c-file (40-47)
+            let i = 2 * self.turn.opposite().as_usize() + (m.to() >= 40) as usize;
+            (self.castle_rooks[i], CASTLE_ROOK_TO[i])
+        })
+    }
+
+    /// the move in UCI notation, e.g. e2e4 or e7e8q. Castling is king takes rook
+    /// in Chess960, e.g. e1h1, otherwise the king's move, e.g. e1g1
+    pub fn uci_label(&self, m: &Move) -> String {
+        let to = match self.castle_rook(m) {
+            Some((rook, _)) if self.chess960 => Square::from_index(rook),
+            _ => m.to_sq(),
+        };
+        let promote = match m.promote_kind() {
+            QUEEN => "q",
+            ROOK => "r",
+            BISHOP => "b",
+            KNIGHT => "n",
+            _ => "",
+        };
+        format!("{}{to}{promote}", m.frm_sq())
+    }
+
+    /// the legal move in UCI notation - see uci_label. Without the promotion
+    /// piece the first promotion matches
+    pub fn parse_uci_move(&self, s: &str) -> Option<Move> {
+        if s.len() < 4 {
+            return None;
         }
+        self.legal_moves()
+            .into_iter()
+            .find(|m| self.uci_label(m).starts_with(s))
+    }
+
+    /// en passant square - if the side to move can capture en passant
+    #[inline(always)]
+    pub fn en_passant_capture_sq(&self) -> Option<u8> {
+        can_capture_en_passant(&self.squares, self.turn, self.en_passant_sq)
+            .then_some(self.en_passant_sq)
     }
 
     #[inline(always)]
     pub fn is_en_passant(&self, m: &Move) -> bool {
         self.en_passant_sq > 0
             && m.to() == self.en_passant_sq
-            && self.bitmaps.pawns & 1 << m.frm() != 0
+            && self.bitmaps.of_kind(PAWN) & 1 << m.frm() != 0
     }
 
     #[inline(always)]
@@ -351,27 +627,24 @@ impl Board {
             Colour::white()
         };
 
-        let mut can_castle = 0;
-        if parts.len() > 2 {
-            for (c, q) in ['K', 'Q', 'k', 'q'].into_iter().zip([
-                CASTLE_W_SHORT,
-                CASTLE_W_LONG,
-                CASTLE_B_SHORT,
-                CASTLE_B_LONG,
-            ]) {
-                if parts[2].contains(c) {
-                    can_castle |= q
-                }
-            }
-        }
+        let (can_castle, castle_rooks) = match parts.get(2) {
+            Some(field) => parse_castling(&squares, field)?,
+            None => (0, STANDARD_ROOKS),
+        };
+        let castle_masks = castle_masks(&squares, can_castle, &castle_rooks);
+        let chess960 = (0..4).any(|i| {
+            can_castle & 1 << i != 0
+                && (castle_rooks[i] != STANDARD_ROOKS[i]
+                    || castle_masks[STANDARD_KINGS[i] as usize] & 1 << i == 0)
+        });
 
         let en_passant_sq = if parts.len() > 3 {
             if parts[3].starts_with('-') {
                 0
             } else {
-                match misc::parse_chess_coord(parts[3]) {
-                    None => return Err("Bad en passant square in fen string".into()),
-                    Some(val) => val,
+                match parts[3].parse::<Square>() {
+                    Err(_) => return Err("Bad en passant square in fen string".into()),
+                    Ok(sq) => sq.index(),
                 }
             }
         } else {
@@ -399,7 +672,8 @@ impl Board {
         let bitmaps = to_bitmaps(&squares);
 
         let end_game_material = abs_material(&from_fen(ROOT_FEN)) / 3;
-        let hash = calc_hash(&squares, turn);
+        let hash = calc_hash(&squares, turn, can_castle, en_passant_sq);
+        let pawn_hash = calc_pawn_hash(&squares);
         let material = material(&squares);
         let rep = HashMap::from([(hash, 1)]);
 
@@ -412,10 +686,15 @@ impl Board {
             log_bms: vec![],
             material,
             hash,
+            pawn_hash,
             en_passant_sq,
             half_move_clock,
             full_move_count,
             rep,
+            style: Style::default(),
+            chess960,
+            castle_rooks,
+            castle_masks,
         })
     }
 
@@ -446,11 +725,28 @@ impl Board {
         s.push_str(if self.turn.is_white() { " w" } else { " b" });
         s.push(' ');
 
+        // X-FEN - the rook's file if it is not the outermost rook on its side of the king
         let castling_rights: String = ['K', 'Q', 'k', 'q']
             .into_iter()
-            .zip([CASTLE_W_SHORT, CASTLE_W_LONG, CASTLE_B_SHORT, CASTLE_B_LONG])
-            .filter(|(_, c)| self.can_castle & c != 0)
-            .map(|(x, _)| x)
+            .enumerate()
+            .filter(|&(i, _)| self.can_castle & 1 << i != 0)
+            .map(|(i, c)| {
+                let rook = Square::from_index(self.castle_rooks[i]);
+                let outermost = File::all()
+                    .filter(|&f| {
+                        if i % 2 == 0 {
+                            f > rook.file()
+                        } else {
+                            f < rook.file()
+                        }
+                    })
+                    .all(|f| self[Square::new(f, rook.rank())] != self[rook]);
+                match (outermost, i < 2) {
+                    (true, _) => c,
+                    (false, true) => rook.file().to_char().to_ascii_uppercase(),
+                    (false, false) => rook.file().to_char(),
+                }
+            })
             .collect();
         s.push_str(if castling_rights.is_empty() {
             "-"
@@ -461,7 +757,7 @@ impl Board {
         // en passant sq
         s.push(' ');
         if self.en_passant_sq > 0 {
-            s.push_str(I2SQ[self.en_passant_sq as usize])
+            s.push_str(Square::from_index(self.en_passant_sq).name())
         } else {
             s.push('-');
         }
@@ -507,6 +803,16 @@ impl Board {
         v
     }
 
+    /// hash key computed from scratch - see Board::hash for the incremental key
+    pub fn calc_hash(&self) -> u64 {
+        calc_hash(
+            &self.squares,
+            self.turn,
+            self.can_castle,
+            self.en_passant_sq,
+        )
+    }
+
     pub fn half_moves(&self) -> usize {
         self.half_move_clock + self.rep.iter().map(|(_, &v)| v).sum::<u8>() as usize
     }
@@ -516,53 +822,37 @@ impl Board {
             self.bitmaps,
             self[m.to() as usize],
             self.hash,
+            self.pawn_hash,
             self.can_castle,
             self.en_passant_sq,
         ));
+        self.update_pawn_hash(m);
+        let old_keys = castle_hash(self.can_castle)
+            ^ en_passant_hash(&self.squares, self.turn, self.en_passant_sq);
         let hash;
-        self[m.to() as usize] = if self.is_castle(m) {
+        let castle = self.castle_rook(m);
+        let p = if let Some((r_frm, r_to)) = castle {
             self.en_passant_sq = 0;
-            let (r_frm, r_to) = if m.to() <= 15 {
-                (m.frm() - 24, m.frm() - 8) // short
-            } else {
-                (m.frm() + 32, m.frm() + 8) // long
-            };
-
-            self.bitmaps.kings |= 1 << m.to();
-            self.bitmaps.kings ^= 1 << m.frm();
-            self.bitmaps.pieces[self.turn.as_usize()] |= 1 << m.to();
-            self.bitmaps.pieces[self.turn.as_usize()] ^= 1 << m.frm();
-            self.bitmaps.pieces[self.turn.as_usize()] |= 1 << r_to;
-            self.bitmaps.pieces[self.turn.as_usize()] ^= 1 << r_frm;
-
-            match self[m.frm() as usize] {
-                WKING => self.can_castle &= !(CASTLE_W_SHORT | CASTLE_W_LONG),
-                BKING => self.can_castle &= !(CASTLE_B_SHORT | CASTLE_B_LONG),
-                _ => {
-                    println!("{}", self);
-                    panic!("not castle {m}...")
-                }
-            }
-
-            hash = self[m.frm() as usize].hashkey(m.to())
-                ^ self[m.frm() as usize].hashkey(m.frm())
-                ^ self[r_frm as usize].hashkey(r_to)
-                ^ self[r_frm as usize].hashkey(r_frm);
-            self[r_to as usize] = self.squares[r_frm as usize]; // move rook
+            let (king, rook) = (self[m.frm() as usize], self[r_frm as usize]);
+            self.bitmaps.toggle(king, m.frm());
+            self.bitmaps.toggle(king, m.to());
+            self.bitmaps.toggle(rook, r_frm);
+            self.bitmaps.toggle(rook, r_to);
+
+            hash = king.hashkey(m.to())
+                ^ king.hashkey(m.frm())
+                ^ rook.hashkey(r_to)
+                ^ rook.hashkey(r_frm);
+            // in Chess960 the king can land on the rook or the rook on the king
             self[r_frm as usize] = EMPTY;
-            self[m.frm() as usize]
+            king
         } else if m.is_promote() {
             self.en_passant_sq = 0;
-            self.bitmaps.pieces[self.turn.as_usize()] |= 1 << m.to();
-            self.bitmaps.pieces[self.turn.as_usize()] ^= 1 << m.frm();
-            self.bitmaps.pawns ^= 1 << m.frm();
-            if self[m.to() as usize] != EMPTY {
-                // capture
-                let c = self[m.to() as usize].colour();
-                self.bitmaps.pieces[c.as_usize()] ^= 1 << m.to();
-            }
-
             let p = Piece::new(m.promote_kind(), self.turn);
+            self.bitmaps.toggle(self[m.frm() as usize], m.frm());
+            self.bitmaps.toggle(self[m.to() as usize], m.to()); // capture
+            self.bitmaps.toggle(p, m.to());
+
             hash = p.hashkey(m.to())
                 ^ self[m.frm() as usize].hashkey(m.frm())
                 ^ self[m.to() as usize].hashkey(m.to());
@@ -577,12 +867,9 @@ impl Board {
                 false => m.frm() - 8, // east
             };
 
-            self.bitmaps.pieces[self.turn.as_usize()] |= 1 << m.to();
-            self.bitmaps.pieces[self.turn.as_usize()] ^= 1 << m.frm();
-            self.bitmaps.pieces[self.turn.opposite().as_usize()] ^= 1 << x;
-            self.bitmaps.pawns |= 1 << m.to();
-            self.bitmaps.pawns ^= 1 << m.frm();
-            self.bitmaps.pawns ^= 1 << x;
+            self.bitmaps.toggle(self[m.frm() as usize], m.frm());
+            self.bitmaps.toggle(self[m.frm() as usize], m.to());
+            self.bitmaps.toggle(self[x as usize], x);
 
             hash = self[m.frm() as usize].hashkey(m.to())
                 ^ self[m.frm() as usize].hashkey(m.frm())
@@ -591,78 +878,55 @@ impl Board {
             self[m.frm() as usize]
         } else {
             self.en_passant_sq = 0;
-            self.bitmaps.pieces[self.turn.as_usize()] |= 1 << m.to();
-            self.bitmaps.pieces[self.turn.as_usize()] ^= 1 << m.frm();
-            let c = self[m.to() as usize].colour();
-            match (self[m.frm() as usize].kind(), self[m.to() as usize].kind()) {
-                (PAWN, PAWN) => {
-                    self.bitmaps.pawns ^= 1 << m.frm();
-                    self.bitmaps.pieces[c.as_usize()] ^= 1 << m.to();
-                }
-                (PAWN, ROOK | BISHOP | QUEEN | KNIGHT) => {
-                    self.bitmaps.pawns |= 1 << m.to();
-                    self.bitmaps.pawns ^= 1 << m.frm();
-                    self.bitmaps.pieces[c.as_usize()] ^= 1 << m.to();
-                }
-                (PAWN, _) => {
-                    if m.frm().abs_diff(m.to()) == 2 {
-                        self.en_passant_sq = m.frm() + 2 * self.turn.as_u8() - 1;
-                    }
-                    self.bitmaps.pawns |= 1 << m.to();
-                    self.bitmaps.pawns ^= 1 << m.frm();
-                }
-                (KING, PAWN) => {
-                    self.bitmaps.pawns ^= 1 << m.to();
-                    self.bitmaps.kings |= 1 << m.to();
-                    self.bitmaps.kings ^= 1 << m.frm();
-                    self.bitmaps.pieces[c.as_usize()] ^= 1 << m.to();
-                }
-                (KING, ROOK | BISHOP | QUEEN | KNIGHT) => {
-                    self.bitmaps.kings |= 1 << m.to();
-                    self.bitmaps.kings ^= 1 << m.frm();
-                    self.bitmaps.pieces[c.as_usize()] ^= 1 << m.to();
-                }
-                (KING, _) => {
-                    self.bitmaps.kings |= 1 << m.to();
-                    self.bitmaps.kings ^= 1 << m.frm();
-                }
-                (_, PAWN) => {
-                    self.bitmaps.pawns ^= 1 << m.to();
-                    self.bitmaps.pieces[c.as_usize()] ^= 1 << m.to()
-                }
-                (_, ROOK | KNIGHT | QUEEN | BISHOP) => {
-                    self.bitmaps.pieces[c.as_usize()] ^= 1 << m.to()
-                }
-                _ => (),
-            }
-            match (self[m.frm() as usize], self[m.to() as usize]) {
-                (WKING, _) => self.can_castle &= !(CASTLE_W_SHORT | CASTLE_W_LONG),
-                (BKING, _) => self.can_castle &= !(CASTLE_B_SHORT | CASTLE_B_LONG),
-                (WROOK, _) if m.frm() == 0 => self.can_castle &= !CASTLE_W_SHORT,
-                (WROOK, _) if m.frm() == 56 => self.can_castle &= !CASTLE_W_LONG,
-                (BROOK, _) if m.frm() == 7 => self.can_castle &= !CASTLE_B_SHORT,
-                (BROOK, _) if m.frm() == 63 => self.can_castle &= !CASTLE_B_LONG,
-                (_, WROOK) if m.to() == 0 => self.can_castle &= !CASTLE_W_SHORT,
-                (_, WROOK) if m.to() == 56 => self.can_castle &= !CASTLE_W_LONG,
-                (_, BROOK) if m.to() == 7 => self.can_castle &= !CASTLE_B_SHORT,
-                (_, BROOK) if m.to() == 63 => self.can_castle &= !CASTLE_B_LONG,
-                _ => (),
+            let p = self[m.frm() as usize];
+            if p.kind() == PAWN && m.frm().abs_diff(m.to()) == 2 {
+                self.en_passant_sq = m.frm() + 2 * self.turn.as_u8() - 1;
             }
+            self.bitmaps.toggle(self[m.to() as usize], m.to()); // capture
+            self.bitmaps.toggle(p, m.frm());
+            self.bitmaps.toggle(p, m.to());
 
-            hash = self[m.frm() as usize].hashkey(m.to())
-                ^ self[m.frm() as usize].hashkey(m.frm())
-                ^ self[m.to() as usize].hashkey(m.to());
-            self[m.frm() as usize]
+            hash = p.hashkey(m.to()) ^ p.hashkey(m.frm()) ^ self[m.to() as usize].hashkey(m.to());
+            p
         };
         self[m.frm() as usize] = EMPTY;
+        self[m.to() as usize] = p;
+        if let Some((_, r_to)) = castle {
+            self[r_to as usize] = Piece::new(ROOK, self.turn);
+        }
+        // moving the king or a rook, or capturing a rook, loses the right - restored by backdate
+        self.can_castle &=
+            !(self.castle_masks[m.frm() as usize] | self.castle_masks[m.to() as usize]);
         self.material += m.val;
         self.rep_inc();
         self.hash ^= hash ^ WHITE_HASH;
         //self.bitmaps = to_bitmaps(&self.squares);
         self.turn.flip();
+        self.hash ^= old_keys
+            ^ castle_hash(self.can_castle)
+            ^ en_passant_hash(&self.squares, self.turn, self.en_passant_sq);
         //self.hash = calc_hash(&self.squares, self.turn); // TMP
     }
 
+    /// call before the move is made
+    fn update_pawn_hash(&mut self, m: &Move) {
+        let (frm, to) = (m.frm(), m.to());
+        let p = self[frm as usize];
+        if p.kind() == PAWN {
+            self.pawn_hash ^= p.hashkey(frm);
+            if !m.is_promote() {
+                self.pawn_hash ^= p.hashkey(to);
+            }
+            if self.is_en_passant(m) {
+                let x = if to > frm { frm + 8 } else { frm - 8 };
+                self.pawn_hash ^= self[x as usize].hashkey(x);
+            }
+        }
+        if self[to as usize].kind() == PAWN {
+            self.pawn_hash ^= self[to as usize].hashkey(to);
+        }
+    }
+
     pub fn backdate(&mut self, m: &Move) {
         let bms = self.log_bms.pop().unwrap();
         let capture;
@@ -670,20 +934,20 @@ impl Board {
             self.bitmaps,
             capture,
             self.hash,
+            self.pawn_hash,
             self.can_castle,
             self.en_passant_sq,
         ) = bms;
         self.turn.flip();
         //self.hash ^= m.hash ^ WHITE_HASH;
         self.rep_dec();
-        if self.is_castle(m) {
-            let (frm, to) = if m.to() <= 15 {
-                (m.frm() - 24, m.frm() - 8) // short
-            } else {
-                (m.frm() + 32, m.frm() + 8) // long
-            };
-            self[frm as usize] = self.squares[to as usize]; // move rook
-            self[to as usize] = EMPTY;
+        if let Some((r_frm, r_to)) = self.castle_rook(m) {
+            self[r_to as usize] = EMPTY;
+            self[m.to() as usize] = EMPTY;
+            self[m.frm() as usize] = Piece::new(KING, self.turn);
+            self[r_frm as usize] = Piece::new(ROOK, self.turn);
+            self.material -= m.val;
+            return;
         }
         self[m.frm() as usize] = if m.is_promote() {
             Piece::new(PAWN, self.turn)
@@ -708,7 +972,20 @@ impl Board {
     }
 
     pub fn eval(&self) -> i16 {
-        let s = self.material + self.score_pawn_structure() + self.mobility();
+        self.eval_with_pawns(self.score_pawn_structure())
+    }
+
+    /// eval with a precomputed pawn structure score, e.g. from the pawn table
+    pub fn eval_with_pawns(&self, pawn_score: i16) -> i16 {
+        let mut s = self.material
+            + pawn_score
+            + (self.mobility() as i32 * self.style.mobility as i32 / 100) as i16;
+        if self.style.king_attack != 0 {
+            s += self.style.king_attack * self.king_attack();
+        }
+        if self.style.exchange != 0 {
+            s += self.exchange_bias();
+        }
         if self.turn.is_white() { s } else { -s }
         //s * (2 * (colour as i16) - 1)
     }
@@ -721,7 +998,7 @@ impl Board {
         let mut pen: i16 = 0;
 
         for c in [WHITE, BLACK] {
-            let bm = self.bitmaps.pawns & self.bitmaps.pieces[c.as_usize()];
+            let bm = self.bitmaps.of(PAWN, c);
 
             // Generate file occupancy bitmap
             let mut file_mask: u8 = 0;
@@ -752,71 +1029,147 @@ impl Board {
                 fm &= fm - 1;
             }
 
-            let score = 20 * double_pawns + 4 * isolated_pawns;
+            let score =
+                self.style.doubled_pawn * double_pawns + self.style.isolated_pawn * isolated_pawns;
             pen += if c.is_white() { -score } else { score };
         }
 
         // passed pawn bonus
-        // just checking a hole has been punched - actual passed requires neighbour
-        // files to be clear as well...
+        let mut passed = self.passed_pawns();
+        while passed != 0 {
+            let sq = passed.trailing_zeros();
+            if self.bitmaps.pieces[WHITE.as_usize()] & 1 << sq != 0 {
+                let rank = (sq % 8) as i16;
+                pen += self.style.passed_pawn * rank * rank;
+            } else {
+                let rank = (7 - (sq % 8)) as i16;
+                pen -= self.style.passed_pawn * rank * rank;
+            }
+            passed &= passed - 1;
+        }
+
+        pen
+    }
+
+    /// the most advanced pawn on a file if no opposing pawn is in front of it.
+    /// Just checking a hole has been punched - actual passed requires neighbour
+    /// files to be clear as well...
+    pub fn passed_pawns(&self) -> u64 {
+        let mut passed = 0;
         for file in 0..8 {
             let mask = 0xFFu64 << (file * 8); // a file, starting from H
 
-            let wp = mask & self.bitmaps.pawns & self.bitmaps.pieces[WHITE.as_usize()];
-            let bp = mask & self.bitmaps.pawns & self.bitmaps.pieces[BLACK.as_usize()];
+            let wp = mask & self.bitmaps.of(PAWN, WHITE);
+            let bp = mask & self.bitmaps.of(PAWN, BLACK);
 
             if wp != 0 && wp.leading_zeros() < bp.leading_zeros() {
-                let sq = 63 - wp.leading_zeros();
-                let rank = (sq % 8) as i16;
-                pen += 2 * rank * rank;
+                passed |= 1 << (63 - wp.leading_zeros());
             }
             if bp != 0 && bp.trailing_zeros() < wp.trailing_zeros() {
-                let sq = bp.trailing_zeros();
-                let rank = (7 - (sq % 8)) as i16;
-                pen -= 2 * rank * rank;
+                passed |= 1 << bp.trailing_zeros();
             }
         }
+        passed
+    }
 
-        pen
+    /// squares attacked by pawns of colour c
+    pub fn pawn_attacks(&self, c: Colour) -> u64 {
+        let mut pawns = self.bitmaps.of(PAWN, c);
+        let mut attacks = 0;
+        while pawns != 0 {
+            attacks |= BM_PAWN_CAPTURES[c.as_usize()][pawns.trailing_zeros() as usize];
+            pawns &= pawns - 1;
+        }
+        attacks
+    }
+
+    /// squares next to the enemy king attacked by officers; white minus black
+    pub fn king_attack(&self) -> i16 {
+        let bm_board =
+            Bitboard(self.bitmaps.pieces[WHITE.as_usize()] | self.bitmaps.pieces[BLACK.as_usize()]);
+        let mut score = 0;
+        for c in [WHITE, BLACK] {
+            let bm_king = self.bitmaps.of(KING, c.opposite());
+            if bm_king == 0 {
+                continue;
+            }
+            let zone = Bitboard(BM_KING_MOVES[bm_king.trailing_zeros() as usize]);
+            let n = [KNIGHT, BISHOP, ROOK, QUEEN]
+                .into_iter()
+                .flat_map(|kind| {
+                    let pieces = Bitboard(self.bitmaps.of(kind, c));
+                    pieces
+                        .into_iter()
+                        .map(move |frm| attacks(kind, frm, bm_board))
+                })
+                .map(|b| (b & zone).count() as i16)
+                .sum::<i16>();
+            score += if c.is_white() { n } else { -n };
+        }
+        score
+    }
+
+    /// bonus for trading down when ahead in material; white minus black
+    fn exchange_bias(&self) -> i16 {
+        if self.material.abs() < 100 {
+            return 0;
+        }
+        let traded = (3 * self.end_game_material - abs_material(&self.squares)).max(0);
+        self.material.signum() * (self.style.exchange as i32 * traded as i32 / 1000) as i16
     }
 
     // true if !colour side can capture colour king
     pub fn in_check(&self, colour: Colour) -> bool {
-        let bm_king = self.bitmaps.kings & self.bitmaps.pieces[colour.as_usize()];
-        let bm_board =
-            self.bitmaps.pieces[BLACK.as_usize()] | self.bitmaps.pieces[WHITE.as_usize()];
-        let opp = colour.opposite().as_usize();
-        self.squares
-            .iter()
-            .enumerate()
-            .filter(|(frm, _)| 1 << frm & self.bitmaps.pieces[opp] != 0)
-            .any(|(frm, &p)| match p.kind() {
-                KNIGHT => BM_KNIGHT_MOVES[frm] & bm_king != 0,
-                KING => BM_KING_MOVES[frm] & bm_king != 0,
-                PAWN => BM_PAWN_CAPTURES[opp][frm] & bm_king != 0,
-                ROOK => ray_check(frm as u8, BM_ROOK_MOVES[frm], bm_board, bm_king),
-                BISHOP => ray_check(frm as u8, BM_BISHOP_MOVES[frm], bm_board, bm_king),
-                QUEEN => ray_check(frm as u8, BM_QUEEN_MOVES[frm], bm_board, bm_king),
-                _ => false,
-            })
+        Bitboard(self.bitmaps.of(KING, colour))
+            .first()
+            .is_some_and(|king| !self.attackers_to(king, colour.opposite()).is_empty())
+    }
+
+    /// true if any of the squares in bb is attacked by colour
+    pub fn is_attacked(&self, bb: Bitboard, colour: Colour) -> bool {
+        bb.into_iter()
+            .any(|sq| !self.attackers_to(sq, colour).is_empty())
+    }
+
+    /// pieces of colour attacking sq - found by looking from sq
+    /// with the moves of a knight, king, pawn, rook and bishop
+    pub fn attackers_to(&self, sq: Square, colour: Colour) -> Bitboard {
+        let occupied = Bitboard(self.bitmaps.pieces[0] | self.bitmaps.pieces[1]);
+        self.attackers(sq, occupied, colour)
     }
 
     pub fn moves(&self, in_check: bool, end_game: bool) -> Vec<Move> {
-        let mut v = Vec::with_capacity(50);
-        self.squares
-            .iter()
-            .enumerate()
-            .filter(|(frm, _)| 1 << frm & self.bitmaps.pieces[self.turn.as_usize()] != 0)
-            .for_each(|(frm, &p)| match p.kind() {
-                KNIGHT => self.knight_moves(&mut v, frm as u8),
-                KING => self.king_moves(&mut v, frm as u8, end_game, in_check),
-                PAWN => self.pawn_moves(&mut v, frm as u8),
-                ROOK => self.ray_moves(&mut v, frm as u8, BM_ROOK_MOVES[frm]),
-                BISHOP => self.ray_moves(&mut v, frm as u8, BM_BISHOP_MOVES[frm]),
-                QUEEN => self.ray_moves(&mut v, frm as u8, BM_QUEEN_MOVES[frm]),
-                _ => (),
-            });
-        v
+        let mut v = MoveList::default();
+        bits(self.bitmaps.pieces[self.turn.as_usize()])
+            .for_each(|frm| self.piece_moves(&mut v, frm as u8, end_game, in_check, Gen::All));
+        v.to_vec()
+    }
+
+    /// squares the pieces (other than pawns) may move to
+    #[inline(always)]
+    fn targets(&self, wanted: Gen) -> u64 {
+        let own = self.bitmaps.pieces[self.turn.as_usize()];
+        let opp = self.bitmaps.pieces[self.turn.opposite().as_usize()];
+        match wanted {
+            Gen::All => !own,
+            Gen::Captures => opp,
+            Gen::Quiets => !(own | opp),
+        }
+    }
+
+    /// pseudo-legal moves of the piece on frm
+    fn piece_moves(&self, v: &mut MoveList, frm: u8, end_game: bool, in_check: bool, wanted: Gen) {
+        let i = frm as usize;
+        let board = self.bitmaps.pieces[0] | self.bitmaps.pieces[1];
+        let targets = self.targets(wanted);
+        match self.squares[i].kind() {
+            KING => self.king_moves(v, frm, end_game, in_check, wanted),
+            PAWN => self.pawn_moves(v, frm, wanted),
+            kind => {
+                let bb = attacks(kind, Square::from_index(frm), Bitboard(board));
+                self.add_moves_from_bitmap(v, frm, bb.0 & targets)
+            }
+        }
     }
 
     /// delta value of moving a piece between two squares and possibly capturing another piece
@@ -828,7 +1181,7 @@ impl Board {
     }
 
     #[inline]
-    fn add_moves_from_bitmap(&self, v: &mut Vec<Move>, frm: u8, mut bitmap: u64) {
+    fn add_moves_from_bitmap(&self, v: &mut MoveList, frm: u8, mut bitmap: u64) {
         while bitmap != 0 {
             let to = bitmap.trailing_zeros() as u8;
             bitmap &= !(1 << to);
@@ -839,23 +1192,9 @@ impl Board {
         }
     }
 
-    #[inline]
-    fn knight_moves(&self, v: &mut Vec<Move>, frm: u8) {
-        let b = BM_KNIGHT_MOVES[frm as usize] & !self.bitmaps.pieces[self.turn.as_usize()];
-        self.add_moves_from_bitmap(v, frm, b);
-    }
-
-    #[inline]
-    fn ray_moves(&self, v: &mut Vec<Move>, frm: u8, moves: u64) {
-        let board = self.bitmaps.pieces[0] | self.bitmaps.pieces[1];
-        let bl = bm_blockers(frm, moves & board);
-        let b = moves & !bl & !self.bitmaps.pieces[self.turn.as_usize()];
-        self.add_moves_from_bitmap(v, frm, b);
-    }
-
-    fn pawn_moves(&self, v: &mut Vec<Move>, frm: u8) {
-        let cap = BM_PAWN_CAPTURES[self.turn.as_usize()][frm as usize]
-            & self.bitmaps.pieces[self.turn.opposite().as_usize()];
+    fn pawn_moves(&self, v: &mut MoveList, frm: u8, wanted: Gen) {
+        let opp = self.bitmaps.pieces[self.turn.opposite().as_usize()];
+        let cap = BM_PAWN_CAPTURES[self.turn.as_usize()][frm as usize] & opp;
         let step1 = 1u64 << (frm + 2 * self.turn.as_u8() - 1)
             & !(self.bitmaps.pieces[0] | self.bitmaps.pieces[1]);
         let bm_board = self.bitmaps.pieces[0] | self.bitmaps.pieces[1];
@@ -864,7 +1203,11 @@ impl Board {
             BLACK if ROW7 & 1 << frm != 0 => (step1 >> 1) & !bm_board,
             _ => 0,
         };
-        let mut b = cap | step1 | step2;
+        let mut b = match wanted {
+            Gen::All => cap | step1 | step2,
+            Gen::Captures => cap | (step1 & (ROW1 | ROW8)),
+            Gen::Quiets => (step1 | step2) & !(ROW1 | ROW8),
+        };
         while b != 0 {
             let to = b.trailing_zeros() as u8;
             b &= !(1 << to);
@@ -894,7 +1237,7 @@ impl Board {
             }
         }
 
-        if self.en_passant_sq > 0 {
+        if self.en_passant_sq > 0 && wanted != Gen::Quiets {
             let lto = self.en_passant_sq + 2 * self.turn.opposite().as_u8() - 1;
             let b = BM_PAWN_CAPTURES[self.turn.as_usize()][frm as usize] & 1 << self.en_passant_sq;
             let to = self.en_passant_sq;
@@ -909,7 +1252,7 @@ impl Board {
         }
     }
 
-    fn king_moves(&self, v: &mut Vec<Move>, frm: u8, end_game: bool, in_check: bool) {
+    fn king_moves(&self, v: &mut MoveList, frm: u8, end_game: bool, in_check: bool, wanted: Gen) {
         let bm_board =
             self.bitmaps.pieces[WHITE.as_usize()] | self.bitmaps.pieces[BLACK.as_usize()];
 
@@ -920,63 +1263,35 @@ impl Board {
             Piece::new(KING, self.turn)
         };
 
-        struct Castle {
-            side: u8,
-            block_mask: u64, // squares between K & R - must be unoccupied
-            rook_frm: u8,
-            rook_to: u8,
-            king_to: u8,
-        }
-
-        const CASTLES: [[Castle; 2]; 2] = [
-            [
-                Castle {
-                    side: CASTLE_B_SHORT,
-                    block_mask: 1 << sq2i("f8") | 1 << sq2i("g8"),
-                    rook_frm: sq2i("h8"),
-                    rook_to: sq2i("f8"),
-                    king_to: sq2i("g8"),
-                },
-                Castle {
-                    side: CASTLE_B_LONG,
-                    block_mask: 1 << sq2i("b8") | 1 << sq2i("c8") | 1 << sq2i("d8"),
-                    rook_frm: sq2i("a8"),
-                    rook_to: sq2i("d8"),
-                    king_to: sq2i("c8"),
-                },
-            ],
-            [
-                Castle {
-                    side: CASTLE_W_SHORT,
-                    block_mask: 1 << sq2i("f1") | 1 << sq2i("g1"),
-                    rook_frm: sq2i("h1"),
-                    rook_to: sq2i("f1"),
-                    king_to: sq2i("g1"),
-                },
-                Castle {
-                    side: CASTLE_W_LONG,
-                    block_mask: 1 << sq2i("d1") | 1 << sq2i("c1") | 1 << sq2i("b1"),
-                    rook_frm: sq2i("a1"),
-                    rook_to: sq2i("d1"),
-                    king_to: sq2i("c1"),
-                },
-            ],
-        ];
-
-        if !in_check && self.bitmaps.kings & 1 << frm != 0 {
-            for c in &CASTLES[self.turn.as_usize()] {
-                if self.can_castle & c.side != 0 && bm_board & c.block_mask == 0 {
+        if !in_check && wanted != Gen::Captures {
+            // the squares between the king, the rook and their destinations must be empty
+            // and the king must not pass through check - the rook may have blocked an attack
+            let opp = self.turn.opposite();
+            let first = 2 * opp.as_usize();
+            for i in (first..first + 2).filter(|&i| self.can_castle & 1 << i != 0) {
+                let (rook_frm, king_to, rook_to) =
+                    (self.castle_rooks[i], CASTLE_KING_TO[i], CASTLE_ROOK_TO[i]);
+                let rook = self[rook_frm as usize];
+                let [frm_sq, king_to_sq, rook_frm_sq, rook_to_sq] =
+                    [frm, king_to, rook_frm, rook_to].map(Square::from_index);
+                let occupied = Bitboard(bm_board) ^ frm_sq.bb() ^ rook_frm_sq.bb();
+                let king_path = between(frm_sq, king_to_sq) | king_to_sq.bb();
+                let rook_path = between(rook_frm_sq, rook_to_sq) | rook_to_sq.bb();
+                if rook == Piece::new(ROOK, self.turn)
+                    && (occupied & (king_path | rook_path)).is_empty()
+                    && king_path
+                        .into_iter()
+                        .all(|sq| self.attackers(sq, occupied, opp).is_empty())
+                {
                     v.push(Move {
-                        data: pack_data(0, frm, c.king_to),
-                        val: p.val(c.king_to) - p.val(frm)
-                            + self[c.rook_frm as usize].val(c.rook_to)
-                            - self[c.rook_frm as usize].val(c.rook_frm),
+                        data: pack_data(CASTLE, frm, king_to),
+                        val: p.val(king_to) - p.val(frm) + rook.val(rook_to) - rook.val(rook_frm),
                     });
                 }
             }
         }
 
-        let mut b = BM_KING_MOVES[frm as usize] & !self.bitmaps.pieces[self.turn.as_usize()];
+        let mut b = BM_KING_MOVES[frm as usize] & self.targets(wanted);
         while b != 0 {
             let to = b.trailing_zeros() as u8;
             b &= !(1 << to);
@@ -991,30 +1306,23 @@ impl Board {
     // count pseudo legal moves - ignoring en passant & castling
     pub fn mobility(&self) -> i16 {
         let bm_board =
-            self.bitmaps.pieces[WHITE.as_usize()] | self.bitmaps.pieces[BLACK.as_usize()];
-        let bm_w = self.bitmaps.pieces[WHITE.as_usize()];
-        let bm_b = self.bitmaps.pieces[BLACK.as_usize()];
-
-        self.squares
-            .iter()
-            .enumerate()
-            .map(|(frm, &p)| match p {
-                WKNIGHT => (BM_KNIGHT_MOVES[frm] & !bm_w).count_ones() as i16,
-                WKING => (BM_KING_MOVES[frm] & !bm_w).count_ones() as i16,
-                //WPAWN => count_pawn_moves(frm as u8, bm_b, bm_board, WHITE),
-                WROOK => count_ray_moves(frm as u8, BM_ROOK_MOVES[frm], bm_board, bm_w),
-                WBISHOP => count_ray_moves(frm as u8, BM_BISHOP_MOVES[frm], bm_board, bm_w),
-                WQUEEN => count_ray_moves(frm as u8, BM_QUEEN_MOVES[frm], bm_board, bm_w),
-                BKNIGHT => -((BM_KNIGHT_MOVES[frm] & !bm_b).count_ones() as i16),
-                BKING => -((BM_KING_MOVES[frm] & !bm_b).count_ones() as i16),
-                //BPAWN => count_pawn_moves(frm as u8, bm_w, bm_board, BLACK),
-                BROOK => -count_ray_moves(frm as u8, BM_ROOK_MOVES[frm], bm_board, bm_b),
-                BBISHOP => -count_ray_moves(frm as u8, BM_BISHOP_MOVES[frm], bm_board, bm_b),
-                BQUEEN => -count_ray_moves(frm as u8, BM_QUEEN_MOVES[frm], bm_board, bm_b),
-                _ => 0,
-            })
-            .sum::<i16>()
-            + self.count_all_pawn_moves()
+            Bitboard(self.bitmaps.pieces[WHITE.as_usize()] | self.bitmaps.pieces[BLACK.as_usize()]);
+        let mut score = self.count_all_pawn_moves();
+        for c in [WHITE, BLACK] {
+            let own = Bitboard(self.bitmaps.pieces[c.as_usize()]);
+            let n = [KNIGHT, BISHOP, ROOK, QUEEN, KING]
+                .into_iter()
+                .flat_map(|kind| {
+                    let pieces = Bitboard(self.bitmaps.of(kind, c));
+                    pieces
+                        .into_iter()
+                        .map(move |frm| attacks(kind, frm, bm_board))
+                })
+                .map(|b| (b & !own).count() as i16)
+                .sum::<i16>();
+            score += if c.is_white() { n } else { -n };
+        }
+        score
     }
 
     // +9  +1 -7
@@ -1027,8 +1335,8 @@ impl Board {
     pub const fn count_all_pawn_moves(&self) -> i16 {
         let bm_board =
             self.bitmaps.pieces[WHITE.as_usize()] | self.bitmaps.pieces[BLACK.as_usize()];
-        let wpawns = self.bitmaps.pawns & self.bitmaps.pieces[WHITE.as_usize()];
-        let bpawns = self.bitmaps.pawns & self.bitmaps.pieces[BLACK.as_usize()];
+        let wpawns = self.bitmaps.of(PAWN, WHITE);
+        let bpawns = self.bitmaps.of(PAWN, BLACK);
         let wcap = ((wpawns << 9) | (wpawns >> 7)) & self.bitmaps.pieces[BLACK.as_usize()];
         let wstep1 = (wpawns << 1) & !bm_board;
         let wstep2 = ((wstep1 & ROW3) << 1) & !bm_board;
@@ -1050,6 +1358,19 @@ impl Board {
     }
 }
 
+/// squares attacked by a knight, bishop, rook, queen or king on frm
+#[inline(always)]
+const fn attacks(kind: u8, frm: Square, occupied: Bitboard) -> Bitboard {
+    match kind {
+        KNIGHT => Bitboard(BM_KNIGHT_MOVES[frm.as_usize()]),
+        BISHOP => bishop_attacks(frm, occupied),
+        ROOK => rook_attacks(frm, occupied),
+        QUEEN => queen_attacks(frm, occupied),
+        KING => Bitboard(BM_KING_MOVES[frm.as_usize()]),
+        _ => Bitboard::EMPTY,
+    }
+}
+
 /// count for just one pawn - unlike count_all_pawn_moves()
 /// this is slower, but does not merge multiple captures of the same enemy piece...
 const fn _count_pawn_moves(frm: u8, bm_opp: u64, bm_board: u64, colour: Colour) -> i16 {
@@ -1063,37 +1384,14 @@ const fn _count_pawn_moves(frm: u8, bm_opp: u64, bm_board: u64, colour: Colour)
     (cap | step1 | step2).count_ones() as i16
 }
 
-#[inline(always)]
-const fn count_ray_moves(frm: u8, moves: u64, bm_board: u64, bm_own: u64) -> i16 {
-    (moves & !bm_own & !bm_blockers(frm, moves & bm_board)).count_ones() as i16
-}
-
-#[inline(always)]
-const fn ray_check(frm: u8, moves: u64, bm_board: u64, bm_king: u64) -> bool {
-    moves & bm_king & !bm_blockers(frm, moves & bm_board) != 0
-}
-
 const fn to_bitmaps(squares: &[Piece]) -> Bitmaps {
     let mut bm = Bitmaps {
         pieces: [0, 0],
-        pawns: 0,
-        kings: 0,
+        kinds: [0; 6],
     };
     let mut i = 0;
     while i < squares.len() {
-        let c = squares[i].colour();
-        match squares[i].kind() {
-            ROOK | KNIGHT | BISHOP | QUEEN => bm.pieces[c.as_usize()] |= 1 << i,
-            PAWN => {
-                bm.pieces[c.as_usize()] |= 1 << i;
-                bm.pawns |= 1 << i
-            }
-            KING => {
-                bm.pieces[c.as_usize()] |= 1 << i;
-                bm.kings |= 1 << i
-            }
-            _ => (),
-        }
+        bm.toggle(squares[i], i as u8);
         i += 1;
     }
     bm
@@ -1119,8 +1417,302 @@ pub const fn abs_material(squares: &[Piece]) -> i16 {
     val
 }
 
-pub const fn calc_hash(squares: &[Piece], colour: Colour) -> u64 {
+/// castling rights key
+pub const fn castle_hash(can_castle: u8) -> u64 {
+    let mut key = 0;
+    let mut i = 0;
+    while i < CASTLE_HASH.len() {
+        if can_castle & 1 << i != 0 {
+            key ^= CASTLE_HASH[i];
+        }
+        i += 1;
+    }
+    key
+}
+
+// Staged move picker for the search - the hash move, good captures, killer
+// moves, quiet moves and finally bad captures. Each stage is generated when the
+// previous one runs out, so most cut nodes never generate the quiet moves.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    HashMove,
+    GenerateCaptures,
+    GoodCaptures,
+    Killers,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+pub struct MovePicker {
+    stage: Stage,
+    hash_move: Option<Move>,
+    killers: [u16; 2], // quiet moves that caused beta cut-offs at the same ply
+    end_game: bool,
+    checks: Option<Checks>,
+    moves: MoveList, // the current stage
+    scores: [i32; MAX_MOVES],
+    next: usize,
+    bad_captures: MoveList,
+}
+
+impl MovePicker {
+    /// the hash move must be legal - see Board::is_legal
+    pub fn new(board: &Board, hash_move: Option<Move>, killers: [u16; 2], end_game: bool) -> Self {
+        MovePicker {
+            stage: Stage::HashMove,
+            hash_move,
+            killers,
+            end_game,
+            checks: board.checks(),
+            moves: MoveList::default(),
+            scores: [0; MAX_MOVES],
+            next: 0,
+            bad_captures: MoveList::default(),
+        }
+    }
+
+    /// next legal move - the board must be in the position the picker was made for
+    pub fn next(&mut self, board: &Board) -> Option<Move> {
+        let hash_data = self.hash_move.map(|m| m.data);
+        loop {
+            match self.stage {
+                Stage::HashMove => {
+                    // no move generation before the hash move - it often cuts off
+                    self.stage = Stage::GenerateCaptures;
+                    if self.hash_move.is_some() {
+                        return self.hash_move;
+                    }
+                }
+                Stage::GenerateCaptures => {
+                    self.stage = Stage::GoodCaptures;
+                    board.generate(&mut self.moves, Gen::Captures, self.end_game, &self.checks);
+                    self.moves.retain(|m| Some(m.data) != hash_data);
+                    for (i, m) in self.moves.iter().enumerate() {
+                        self.scores[i] = board.capture_score(m);
+                    }
+                }
+                Stage::GoodCaptures => match self.pick() {
+                    Some(m) if !board.is_good_capture(&m) => self.bad_captures.push(m),
+                    Some(m) => return Some(m),
+                    None => {
+                        self.stage = Stage::Killers;
+                        self.next = 0;
+                    }
+                },
+                Stage::Killers if self.next < self.killers.len() => {
+                    self.next += 1;
+                    if let Some(m) = self.killer(board, self.killers[self.next - 1]) {
+                        return Some(m);
+                    }
+                }
+                Stage::Killers => {
+                    self.stage = Stage::Quiets;
+                    self.next = 0;
+                    self.moves.clear();
+                    board.generate(&mut self.moves, Gen::Quiets, self.end_game, &self.checks);
+                    let killers = self.killers;
+                    self.moves
+                        .retain(|m| Some(m.data) != hash_data && !killers.contains(&m.data));
+                    let sign = if board.turn.is_white() { 1 } else { -1 };
+                    for (i, m) in self.moves.iter().enumerate() {
+                        self.scores[i] = sign * m.val as i32;
+                    }
+                }
+                Stage::Quiets => match self.pick() {
+                    Some(m) => return Some(m),
+                    None => {
+                        self.stage = Stage::BadCaptures;
+                        self.next = 0;
+                    }
+                },
+                Stage::BadCaptures if self.next < self.bad_captures.len() => {
+                    self.next += 1;
+                    return Some(self.bad_captures[self.next - 1]);
+                }
+                Stage::BadCaptures => self.stage = Stage::Done,
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    /// highest scoring of the remaining moves - one selection sort step
+    fn pick(&mut self) -> Option<Move> {
+        if self.next >= self.moves.len() {
+            return None;
+        }
+        let best = (self.next..self.moves.len())
+            .max_by_key(|&i| (self.scores[i], std::cmp::Reverse(i)))
+            .unwrap();
+        self.moves.swap(self.next, best);
+        self.scores.swap(self.next, best);
+        self.next += 1;
+        Some(self.moves[self.next - 1])
+    }
+
+    /// the killer move, if it is a legal quiet move in this position
+    fn killer(&self, board: &Board, data: u16) -> Option<Move> {
+        if data == 0 || Some(data) == self.hash_move.map(|m| m.data) {
+            return None;
+        }
+        let m = board.move_from_encoding(data)?;
+        let quiet = board[m.to() as usize] == EMPTY && !board.is_en_passant(&m) && !m.is_promote();
+        let legal = match &self.checks {
+            Some(checks) => board.is_legal_with(&m, checks),
+            None => true,
+        };
+        (quiet && legal).then_some(m)
+    }
+}
+
+/// material value for ordering captures - the king is never lost in an exchange
+const fn exchange_value(kind: u8) -> i32 {
+    match kind {
+        PAWN => 100,
+        KNIGHT | BISHOP => 300,
+        ROOK => 500,
+        QUEEN => 900,
+        _ => 0,
+    }
+}
+
+/// castling rights and rook squares of a FEN castling field - KQkq, X-FEN or Shredder-FEN.
+/// K/Q take the outermost rook on that side of the king, a file letter names the rook.
+/// Rights without a king and rook on the back rank are dropped
+fn parse_castling(squares: &[Piece], field: &str) -> Result<(u8, [u8; 4]), String> {
+    let mut can_castle = 0;
+    let mut rooks = STANDARD_ROOKS;
+    for c in field.chars().filter(|&c| c != '-') {
+        let colour = if c.is_ascii_uppercase() { WHITE } else { BLACK };
+        let rank = if colour.is_white() {
+            Rank::FIRST
+        } else {
+            Rank::EIGHTH
+        };
+        let on_rank = |kind| {
+            move |&f: &File| squares[Square::new(f, rank).as_usize()] == Piece::new(kind, colour)
+        };
+        let Some(king) = File::all().find(on_rank(KING)) else {
+            continue;
+        };
+        let rook = match c.to_ascii_lowercase() {
+            'k' => File::all()
+                .rev()
+                .take_while(|&f| f > king)
+                .find(on_rank(ROOK)),
+            'q' => File::all().take_while(|&f| f < king).find(on_rank(ROOK)),
+            f => match File::from_char(f) {
+                Some(f) => Some(f).filter(on_rank(ROOK)),
+                None => return Err(format!("Bad castling rights '{field}' in fen string")),
+            },
+        };
+        if let Some(f) = rook.filter(|&f| f != king) {
+            let i = 2 * colour.opposite().as_usize() + (f < king) as usize;
+            can_castle |= 1 << i;
+            rooks[i] = Square::new(f, rank).index();
+        }
+    }
+    Ok((can_castle, rooks))
+}
+
+/// the castling rights lost by moving from or to each square - the squares of the
+/// king and the castling rooks
+fn castle_masks(squares: &[Piece], can_castle: u8, rooks: &[u8; 4]) -> [u8; 64] {
+    let mut masks = [0; 64];
+    for (i, &rook) in rooks
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| can_castle & 1 << i != 0)
+    {
+        let king = Piece::new(KING, if i < 2 { WHITE } else { BLACK });
+        if let Some(k) = squares.iter().position(|&p| p == king) {
+            masks[k] |= 1 << i;
+        }
+        masks[rook as usize] |= 1 << i;
+    }
+    masks
+}
+
+/// FEN of Chess960 start position n, 0-959 - Scharnagl's numbering, 518 is the
+/// standard position
+pub fn chess960_fen(n: usize) -> String {
+    let n = n % 960;
+    let mut rank = [' '; 8];
+    rank[2 * (n % 4) + 1] = 'b'; // light square
+    rank[2 * (n / 4 % 4)] = 'b';
+    // the others on the nth empty square
+    let mut place = |piece: char, nth: usize| {
+        let f = (0..8).filter(|&f| rank[f] == ' ').nth(nth).unwrap();
+        rank[f] = piece;
+    };
+    let knights = [
+        (0, 0),
+        (0, 1),
+        (0, 2),
+        (0, 3),
+        (1, 1),
+        (1, 2),
+        (1, 3),
+        (2, 2),
+        (2, 3),
+        (3, 3),
+    ];
+    let rest = n / 16;
+    place('q', rest % 6);
+    let (n1, n2) = knights[rest / 6];
+    place('n', n1);
+    place('n', n2); // counted after the 1st knight is placed
+    for piece in ['r', 'k', 'r'] {
+        place(piece, 0);
+    }
+    let black: String = rank.iter().collect();
+    format!(
+        "{black}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1",
+        black.to_uppercase()
+    )
+}
+
+/// true if the side to move (colour) has a pawn that can capture en passant
+pub const fn can_capture_en_passant(squares: &[Piece], colour: Colour, en_passant_sq: u8) -> bool {
+    const fn is_pawn(p: Piece, colour: Colour) -> bool {
+        match p {
+            WPAWN => colour.is_white(),
+            BPAWN => !colour.is_white(),
+            _ => false,
+        }
+    }
+
+    if en_passant_sq == 0 {
+        return false;
+    }
+    // the pawn that moved two squares
+    let pushed = if colour.is_white() {
+        en_passant_sq - 1
+    } else {
+        en_passant_sq + 1
+    } as usize;
+    (pushed >= 8 && is_pawn(squares[pushed - 8], colour))
+        || (pushed < 56 && is_pawn(squares[pushed + 8], colour))
+}
+
+/// en passant key - only if the side to move (colour) has a pawn that can capture
+pub const fn en_passant_hash(squares: &[Piece], colour: Colour, en_passant_sq: u8) -> u64 {
+    if can_capture_en_passant(squares, colour, en_passant_sq) {
+        EN_PASSANT_HASH[en_passant_sq as usize / 8]
+    } else {
+        0
+    }
+}
+
+pub const fn calc_hash(
+    squares: &[Piece],
+    colour: Colour,
+    can_castle: u8,
+    en_passant_sq: u8,
+) -> u64 {
     let mut key = if colour.is_white() { WHITE_HASH } else { 0 };
+    key ^= castle_hash(can_castle) ^ en_passant_hash(squares, colour, en_passant_sq);
 
     let mut i = 0;
     while i < squares.len() {
@@ -1133,6 +1725,18 @@ pub const fn calc_hash(squares: &[Piece], colour: Colour) -> u64 {
     key
 }
 
+pub const fn calc_pawn_hash(squares: &[Piece]) -> u64 {
+    let mut key = 0;
+    let mut i = 0;
+    while i < squares.len() {
+        if squares[i].kind() == PAWN {
+            key ^= squares[i].hashkey(i as u8);
+        }
+        i += 1;
+    }
+    key
+}
+
 fn from_fen(s: &str) -> [Piece; 64] {
     let mut squares = [EMPTY; 64];
     let mut offset = 0i16;
@@ -1156,21 +1760,72 @@ fn from_fen(s: &str) -> [Piece; 64] {
 #[cfg(test)]
 mod tests {
     use crate::benchmark::*;
+    use crate::bitmaps::{Bitboard, ROW2};
+    use crate::mgen::{pack_data, to_bitmaps};
+    use crate::misc::sq2i;
+    use crate::square::Square;
     use crate::*;
 
     #[test]
     fn test_hash() -> Result<(), String> {
         let board = Board::from_fen(ROOT_FEN)?;
         let mut game = Game::new(board);
-        let hash = calc_hash(&game.board.squares, game.board.turn);
-        assert_eq!(hash, game.board.hash);
+        assert_eq!(game.board.calc_hash(), game.board.hash);
 
         let moves = game.board.legal_moves();
         game.make_move(moves[0]);
 
-        let hash = calc_hash(&game.board.squares, game.board.turn);
-        assert_eq!(hash, game.board.hash);
+        assert_eq!(game.board.calc_hash(), game.board.hash);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_hash() -> Result<(), String> {
+        use rand::{SeedableRng, rngs::StdRng, seq::IndexedRandom};
+        let mut rng = StdRng::seed_from_u64(7);
+        let fens = [
+            ROOT_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            GUNDERSEN_FAUL[1].0,
+            BRATKO_KOPEC[9].0,
+        ];
+        for fen in fens {
+            for _ in 0..20 {
+                let mut board = Board::from_fen(fen)?;
+                let start = board.hash;
+                let mut line = vec![];
+                for _ in 0..40 {
+                    let moves = board.legal_moves();
+                    let Some(m) = moves.choose(&mut rng) else {
+                        break;
+                    };
+                    board.update(m);
+                    line.push(*m);
+                    assert_eq!(board.hash, board.calc_hash(), "{}", board.to_fen());
+                }
+                for m in line.iter().rev() {
+                    board.backdate(m);
+                    assert_eq!(board.hash, board.calc_hash());
+                }
+                assert_eq!(board.hash, start);
+            }
+        }
+        Ok(())
+    }
 
+    #[test]
+    fn test_hash_castling_en_passant() -> Result<(), String> {
+        let hash = |fen| Board::from_fen(fen).map(|b| b.hash);
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_ne!(hash(fen)?, hash("r3k2r/8/8/8/8/8/8/R3K2R w Kkq - 0 1")?);
+        assert_ne!(hash(fen)?, hash("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1")?);
+
+        // en passant only counts if a capture is possible
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1";
+        assert_ne!(hash(fen)?, hash("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1")?);
+        let fen = "4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1";
+        assert_eq!(hash(fen)?, hash("4k3/8/8/3p4/4P3/8/8/4K3 w - d6 0 1")?);
         Ok(())
     }
 
@@ -1197,6 +1852,39 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_king_attack() {
+        let mut board = Board::from_fen("7k/8/8/8/8/8/8/K5Q1 w - - 0 1").expect("bad fen");
+        assert_eq!(board.king_attack(), 2); // g7, g8
+        let e = board.eval();
+        board.style.king_attack = 8;
+        assert_eq!(board.eval(), e + 16);
+    }
+
+    #[test]
+    fn test_move_from_encoding() {
+        let board = Board::from_fen("8/1P6/8/8/8/8/8/k6K w - - 0 1").expect("bad fen");
+        let (b7, b8) = (sq2i("b7"), sq2i("b8"));
+        let m = board.move_from_encoding(pack_data(PROMOTE_KNIGHT, b7, b8));
+        assert_eq!(m.map(|m| m.promote_kind()), Some(KNIGHT));
+        assert!(board.move_from_encoding(pack_data(0, b7, b8)).is_none()); // must promote
+        assert!(
+            board
+                .move_from_encoding(pack_data(0, b7, sq2i("b5")))
+                .is_none()
+        );
+        assert!(
+            board
+                .move_from_encoding(pack_data(0, sq2i("a1"), sq2i("a2")))
+                .is_none()
+        ); // not our piece
+        assert!(
+            board
+                .move_from_encoding(pack_data(0, sq2i("h1"), sq2i("h2")))
+                .is_some()
+        );
+    }
+
     #[test]
     fn test_white_pawn_moves_start_pos() {
         let board = Board::from_fen("8/8/8/8/8/8/PPPPPPPP/8 w - - 0 1").expect("bad fen");
@@ -1228,4 +1916,370 @@ mod tests {
         let board = Board::from_fen("8/8/8/8/8/8/P7/P7 w - - 0 1").expect("bad fen");
         assert_eq!(board.count_all_pawn_moves(), 2);
     }
+
+    #[test]
+    fn test_castle_transit() -> Result<(), String> {
+        // f1 attacked - no short castle, d8 attacked - no long castle
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/5r2/R3K2R w KQkq - 0 1")?;
+        let moves: Vec<String> = board.legal_moves().iter().map(|m| m.to_string()).collect();
+        assert!(moves.contains(&"e1c1".to_string()));
+        assert!(!moves.contains(&"e1g1".to_string()));
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R2RK2R b KQkq - 0 1")?;
+        let moves: Vec<String> = board.legal_moves().iter().map(|m| m.to_string()).collect();
+        assert!(moves.contains(&"e8g8".to_string()));
+        assert!(!moves.contains(&"e8c8".to_string()));
+
+        // published perft counts
+        for (fen, depth, n) in [
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", 3, 13744),
+            ("r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1", 4, 1720476),
+            ("r3k2r/1b4bq/8/8/8/8/7B/R3K2R w KQkq - 0 1", 4, 1274206), // rook captures
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                3,
+                97862,
+            ),
+        ] {
+            let mut board = Board::from_fen(fen)?;
+            assert_eq!(board.perft(depth), n, "{fen}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_castling_rights() -> Result<(), String> {
+        let mut board = Board::from_fen("r3k2r/6P1/8/8/8/8/8/R3K2R w KQkq - 0 1")?;
+        let find = |board: &mut Board, s: &str| {
+            board
+                .legal_moves()
+                .into_iter()
+                .find(|m| m.to_string() == s)
+                .unwrap()
+        };
+        // rook takes rook - both lose the right
+        let m = find(&mut board, "a1a8");
+        board.update(&m);
+        assert_eq!(board.can_castle, CASTLE_W_SHORT | CASTLE_B_SHORT);
+        assert!(board.to_fen().contains(" Kk "));
+        assert_eq!(board.hash, board.calc_hash());
+        board.backdate(&m);
+        assert_eq!(board.can_castle, 0b1111);
+
+        // promotion capturing a rook
+        let m = find(&mut board, "g7h8=Q");
+        board.update(&m);
+        assert_eq!(
+            board.can_castle,
+            CASTLE_W_SHORT | CASTLE_W_LONG | CASTLE_B_LONG
+        );
+        assert_eq!(board.hash, board.calc_hash());
+        board.backdate(&m);
+
+        // king move
+        let m = find(&mut board, "e1d1");
+        board.update(&m);
+        assert_eq!(board.can_castle, CASTLE_B_SHORT | CASTLE_B_LONG);
+        board.backdate(&m);
+        assert_eq!(board.can_castle, 0b1111);
+        assert_eq!(board.hash, board.calc_hash());
+        Ok(())
+    }
+
+    #[test]
+    fn test_perft() -> Result<(), String> {
+        // published counts - https://www.chessprogramming.org/Perft_Results
+        for (fen, counts) in [
+            (ROOT_FEN, &[20, 400, 8902, 197281][..]),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                &[48, 2039, 97862],
+            ),
+            // en passant, pins along the rank
+            (
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                &[14, 191, 2812, 43238, 674624],
+            ),
+            // promotions
+            (
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                &[6, 264, 9467, 422333],
+            ),
+            (
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                &[44, 1486, 62379],
+            ),
+            (
+                "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+                &[46, 2079, 89890],
+            ),
+            // en passant capture exposing the king
+            (
+                "8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1",
+                &[15, 126, 1928, 13931],
+            ),
+        ] {
+            let mut board = Board::from_fen(fen)?;
+            let hash = board.hash;
+            for (depth, &n) in counts.iter().enumerate() {
+                assert_eq!(board.perft(depth + 1), n, "{fen} depth {}", depth + 1);
+            }
+            assert_eq!(board.hash, hash);
+        }
+
+        let mut board = Board::from_fen(ROOT_FEN)?;
+        let divide = board.divide(3);
+        assert_eq!(divide.len(), 20);
+        assert_eq!(divide.iter().map(|(_, n)| n).sum::<usize>(), 8902);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chess960() -> Result<(), String> {
+        // https://www.chessprogramming.org/Chess960_Perft_Results
+        for (fen, counts) in [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                [21, 528, 12189, 326672],
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                [21, 807, 18002, 667366],
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+                [20, 479, 10471, 273318],
+            ),
+        ] {
+            let mut board = Board::from_fen(fen)?;
+            assert!(board.chess960);
+            for (depth, &n) in counts.iter().enumerate() {
+                assert_eq!(board.perft(depth + 1), n, "{fen} depth {}", depth + 1);
+            }
+        }
+
+        // the king lands on the rook, stays put, or the rook lands on the king's square
+        fn walk(board: &mut Board, depth: usize) {
+            assert_eq!(board.hash, board.calc_hash(), "{}", board.to_fen());
+            assert_eq!(board.bitmaps, to_bitmaps(&board.squares));
+            if depth > 0 {
+                for m in board.legal_moves() {
+                    board.update(&m);
+                    walk(board, depth - 1);
+                    board.backdate(&m);
+                }
+            }
+        }
+        for back_rank in ["5rkr", "4rkr1", "1r2kr2", "1rk3r1", "r4kr1"] {
+            let fen = format!(
+                "{back_rank}/p7/8/8/8/8/P7/{} w KQkq - 0 1",
+                back_rank.to_uppercase()
+            );
+            walk(&mut Board::from_fen(&fen)?, 3);
+        }
+
+        // X-FEN - a file letter for a rook that is not the outermost
+        let board = Board::from_fen("rr2k3/8/8/8/8/8/8/RR2K3 w Bq - 0 1")?;
+        assert_eq!(board.can_castle, CASTLE_W_LONG | CASTLE_B_LONG);
+        assert!(board.to_fen().contains(" Bq "));
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 w X - 0 1").is_err());
+        assert_eq!(
+            Board::from_fen("4k3/8/8/8/8/8/8/4K3 w KQkq - 0 1")?.can_castle,
+            0
+        );
+
+        // castling in UCI notation
+        let mut board = Board::from_fen("1r2kr2/pppppppp/8/8/8/8/PPPPPPPP/1R2KR2 w KQkq - 0 1")?;
+        let m = board.parse_uci_move("e1f1").unwrap();
+        assert!(board.is_castle(&m) && m.to_string() == "e1g1");
+        assert_eq!(board.castle_rook(&m), Some((sq2i("f1"), sq2i("f1"))));
+        board.update(&m);
+        assert!(
+            board
+                .to_fen()
+                .starts_with("1r2kr2/pppppppp/8/8/8/8/PPPPPPPP/1R3RK1 b kq ")
+        );
+        board.backdate(&m);
+        let mut board = Board::from_fen(ROOT_FEN)?;
+        assert!(!board.chess960);
+        board.update(&board.parse_uci_move("g1f3").unwrap());
+        let m = board.parse_uci_move("e7e5").unwrap();
+        assert_eq!(board.uci_label(&m), "e7e5");
+
+        // Scharnagl numbering
+        assert_eq!(chess960_fen(518), ROOT_FEN);
+        assert!(chess960_fen(0).starts_with("bbqnnrkr/"));
+        let positions: std::collections::HashSet<String> = (0..960).map(chess960_fen).collect();
+        assert_eq!(positions.len(), 960);
+        Ok(())
+    }
+
+    #[test]
+    fn test_legal_moves() -> Result<(), String> {
+        // same moves as making every pseudo-legal move and testing for check
+        fn compare(board: &mut Board, depth: usize) {
+            let in_check = board.in_check(board.turn);
+            let mut expected = Vec::new();
+            for m in board.moves(in_check, false) {
+                board.update(&m);
+                if !board.in_check(board.turn.opposite()) {
+                    expected.push(m);
+                }
+                board.backdate(&m);
+            }
+            let legal = board.legal_moves();
+            assert_eq!(legal, expected, "{}", board.to_fen());
+            if depth > 1 {
+                for m in legal {
+                    board.update(&m);
+                    compare(board, depth - 1);
+                    board.backdate(&m);
+                }
+            }
+        }
+
+        for fen in [
+            ROOT_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1",
+            // en passant: pinned along the rank, capturing the checker
+            "8/8/8/KPp4r/8/8/8/k7 w - c6 0 1",
+            "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+        ] {
+            compare(&mut Board::from_fen(fen)?, 3);
+        }
+
+        let moves = |fen: &str| -> Result<Vec<String>, String> {
+            let board = Board::from_fen(fen)?;
+            Ok(board.legal_moves().iter().map(|m| m.to_string()).collect())
+        };
+        assert!(!moves("8/8/8/KPp4r/8/8/8/k7 w - c6 0 1")?.contains(&"b5c6".to_string()));
+        assert!(moves("8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1")?.contains(&"e4d3".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_attackers_to() -> Result<(), String> {
+        let sq = |s: &str| Square::parse(s).unwrap();
+        let board = Board::default();
+        let bb: Bitboard = ["e2", "g2", "g1"].into_iter().map(sq).collect();
+        assert_eq!(board.attackers_to(sq("f3"), WHITE), bb);
+        assert_eq!(board.attackers_to(sq("f3"), BLACK), Bitboard::EMPTY);
+
+        // sliders are blocked, the king and pawns attack next to them
+        let board = Board::from_fen("4k3/8/8/3q4/4P3/8/3R4/3K4 w - - 0 1")?;
+        let (d1, d2, d5) = (sq("d1"), sq("d2"), sq("d5"));
+        assert_eq!(board.attackers_to(d5, WHITE), d2.bb() | sq("e4").bb());
+        assert_eq!(board.attackers_to(d1, BLACK), Bitboard::EMPTY);
+        assert_eq!(board.attackers_to(d2, BLACK), d5.bb());
+        assert_eq!(board.attackers_to(d2, WHITE), d1.bb());
+        assert!(!board.in_check(WHITE));
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_bitmaps() -> Result<(), String> {
+        // update/backdate keep the bitmaps in step with the squares
+        fn walk(board: &mut Board, depth: usize) {
+            assert_eq!(
+                board.bitmaps,
+                to_bitmaps(&board.squares),
+                "{}",
+                board.to_fen()
+            );
+            if depth == 0 {
+                return;
+            }
+            for m in board.legal_moves() {
+                let before = board.bitmaps;
+                board.update(&m);
+                walk(board, depth - 1);
+                board.backdate(&m);
+                assert_eq!(board.bitmaps, before);
+            }
+        }
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ] {
+            walk(&mut Board::from_fen(fen)?, 3);
+        }
+        let board = Board::default();
+        assert_eq!(board.bitmaps.of(PAWN, WHITE), ROW2);
+        assert_eq!(board.bitmaps.of_kind(ROOK | QUEEN).count_ones(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_picker() -> Result<(), String> {
+        // every legal move exactly once - hash move first, killers after the good captures
+        fn compare(board: &mut Board, depth: usize) {
+            let legal = board.legal_moves();
+            let quiet: Vec<Move> = legal
+                .iter()
+                .filter(|m| {
+                    board[m.to() as usize] == EMPTY && !board.is_en_passant(m) && !m.is_promote()
+                })
+                .copied()
+                .collect();
+            let hash_move = legal.last().copied();
+            let killer = quiet.first().map_or(0, |m| m.data);
+            let mut picker = MovePicker::new(board, hash_move, [killer, 0xfff], false);
+            let mut picked = Vec::new();
+            if let Some(m) = hash_move {
+                assert_eq!(picker.next(board), Some(m));
+                assert!(picker.moves.is_empty()); // captures are generated after the hash move
+                picked.push(m.data);
+            }
+            while let Some(m) = picker.next(board) {
+                picked.push(m.data);
+            }
+            assert_eq!(picked.first(), hash_move.map(|m| m.data).as_ref());
+            let mut expected: Vec<u16> = legal.iter().map(|m| m.data).collect();
+            expected.sort_unstable();
+            let mut sorted = picked.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, expected, "{}", board.to_fen());
+            // the killer comes before the other quiet moves
+            let position = |data: u16| picked.iter().position(|&d| d == data);
+            let hash_data = hash_move.map(|m| m.data);
+            if Some(killer) != hash_data {
+                assert!(
+                    quiet
+                        .iter()
+                        .filter(|m| Some(m.data) != hash_data)
+                        .all(|m| position(m.data) >= position(killer))
+                );
+            }
+            if depth > 1 {
+                for m in legal {
+                    board.update(&m);
+                    compare(board, depth - 1);
+                    board.backdate(&m);
+                }
+            }
+        }
+
+        for fen in [
+            ROOT_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+        ] {
+            compare(&mut Board::from_fen(fen)?, 2);
+        }
+
+        // most valuable victim first - the defended pawn taken by the queen comes last
+        let board = Board::from_fen("r3k3/8/p2rq3/2P5/3N4/8/8/Q6K w - - 0 1")?;
+        let mut picker = MovePicker::new(&board, None, [0; 2], false);
+        let mut picked = Vec::new();
+        while let Some(m) = picker.next(&board) {
+            picked.push(m.to_string());
+        }
+        assert_eq!(picked[..2], ["d4e6", "c5d6"]);
+        assert_eq!(picked.last().unwrap(), "a1a6");
+        Ok(())
+    }
 }
@@ -1,3 +1,5 @@
+use crate::square::Square;
+
 // convert board move coordinates "d2d4" to int tuple
 pub fn str2move(s: &str) -> Option<(u8, u8)> {
     if s.len() < 4 {
@@ -14,29 +16,12 @@ pub const fn sq2i(coord: &str) -> u8 {
     parse_chess_coord(coord).unwrap()
 }
 
+/// board index of a square, e.g. "a4" => 59 - see Square::parse
 pub const fn parse_chess_coord(coord: &str) -> Option<u8> {
-    if coord.len() != 2 {
-        return None; // 2 characters
-    }
-
-    let bytes = coord.as_bytes();
-    let file = bytes[0]; // column letter, e.g., 'a'
-    let rank = bytes[1]; // row number, e.g., '4'
-
-    // a-h, 1-8
-    if file < b'a' || file > b'h' || rank < b'1' || rank > b'8' {
-        return None; // Invalid input
+    match Square::parse(coord) {
+        Some(sq) => Some(sq.index()),
+        None => None,
     }
-
-    // if !(b'a'..=b'h').contains(&file) || !(b'1'..=b'8').contains(&rank) {
-    //     return None; // Invalid input
-    // }
-
-    let col = file - b'a';
-    let row = rank - b'1';
-
-    let sq = (7 - col) * 8 + row;
-    Some(sq)
 }
 
 #[cfg(test)]
@@ -1,63 +1,626 @@
-// file generated by bin/build_openings.rs
-// copy to src/openings.rs
-
-pub fn library_moves(hash: u64) -> &'static [(u8, u8)] {
-    match hash {
-        15047875433102900898 => &[(48, 42)][0..1],
-        15882364312906191476 => &[(59, 50)][0..1],
-        10010926395549307362 => &[(8, 18)][0..1],
-        3977774972637134705 => &[(23, 30), (21, 27)][0..2],
-        6948262538238184177 => &[(8, 18)][0..1],
-        4866306838867053613 => &[(38, 45)][0..1],
-        14873754420231859358 => &[(28, 35), (55, 38)][0..2],
-        12349295847165033790 => &[(15, 21)][0..1],
-        6643485763186563029 => &[(42, 27)][0..1],
-        3374601416427889607 => &[(38, 36), (15, 21)][0..2],
-        18284267297776810397 => &[(31, 15)][0..1],
-        6312011446196548583 => &[(15, 21)][0..1],
-        16483277466833709741 => &[(30, 29)][0..1],
-        16918852456872332189 => &[(32, 41)][0..1],
-        9850006481824754920 => &[(55, 45)][0..1],
-        10614295857992240999 => &[(48, 42), (8, 18)][0..2],
-        7475193447767049553 => &[(23, 30)][0..1],
-        3049123671999485836 => &[(40, 12)][0..1],
-        13147096180447507526 => &[(47, 54)][0..1],
-        8100172176921147596 => &[(48, 42)][0..1],
-        1105785145434099483 => &[(41, 43)][0..1],
-        5207737121055328917 => &[(23, 51)][0..1],
-        8443220267327177804 => &[(54, 52)][0..1],
-        16919744863200515863 => &[(54, 53)][0..1],
-        12712993849330914419 => &[(25, 27), (33, 35)][0..2],
-        18106768372560934827 => &[(30, 28), (30, 29), (46, 45), (46, 44)][0..4],
-        6845174091153575044 => &[(33, 35)][0..1],
-        1332123411865855445 => &[(38, 36)][0..1],
-        9519688773356617913 => &[(38, 36)][0..1],
-        1920191603941357325 => &[(33, 35)][0..1],
-        10143788304400667233 => &[(33, 35)][0..1],
-        9287066229110975072 => &[(47, 20)][0..1],
-        7020172856330130529 => &[(18, 35)][0..1],
-        854802618469694288 => &[(15, 21)][0..1],
-        13285094151722027278 => &[(9, 10)][0..1],
-        5530928974406565598 => &[(41, 43)][0..1],
-        4395969034642167890 => &[(36, 27)][0..1],
-        11004173261521566986 => &[(16, 9)][0..1],
-        7138222008423559680 => &[(16, 52)][0..1],
-        14048603644703401455 => &[(15, 21)][0..1],
-        14181594723008632702 => &[(16, 24)][0..1],
-        5824119965887650602 => &[(33, 35)][0..1],
-        1712000836638999855 => &[(38, 37)][0..1],
-        12058698769378122600 => &[(30, 29)][0..1],
-        13855235223115283248 => &[(44, 35)][0..1],
-        8648944904443331146 => &[(62, 61)][0..1],
-        16391657017564711219 => &[(24, 8)][0..1],
-        2176314626743882407 => &[(23, 30)][0..1],
-        1868888710802494081 => &[(55, 45), (38, 37)][0..2],
-        17541374392054172050 => &[(23, 30)][0..1],
-        17507329128050159319 => &[(38, 37)][0..1],
-        7050349492110348345 => &[(52, 59), (52, 45)][0..2],
-        16575931647895002528 => &[(48, 42)][0..1],
-        7944543423204400479 => &[(8, 18)][0..1],
-        13531708520770561916 => &[(41, 42)][0..1],
-        _ => &[][0..0],
+// Opening books - loaded at runtime from text files, see books/openings.txt.
+// Each line is an opening played from the start position, moves in UCI (e2e4) or
+// PGN (1. e4 e5 2. Nf3) notation. A move can carry a weight - e2e4:5 (default 1).
+// Every move of a line adds its weight to the (position, move) pair, so lines and
+// books are merged by adding weights. '#' starts a comment.
+// Moves are validated against legal_moves when the book is loaded.
+
+use crate::Game;
+use crate::mgen::{Board, Move};
+use crate::polyglot::{Keys, PolyglotBook};
+use rand::random;
+use std::collections::HashMap;
+
+/// the default book - compiled in, so the engine has a book without any files
+const BUILTIN: &str = include_str!("../books/openings.txt");
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BookMove {
+    pub data: u16, // Move::data
+    pub weight: u32,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct OpeningBook {
+    positions: HashMap<u64, Vec<BookMove>>, // Board::hash -> moves
+}
+
+/// strip check & annotation symbols - Nf3+ / e4!? -> Nf3 / e4
+fn strip_san(s: &str) -> &str {
+    s.trim_end_matches(['+', '#', '!', '?'])
+}
+
+/// find the legal move written as s - UCI or SAN
+fn parse_move(game: &mut Game, s: &str) -> Option<Move> {
+    if let Some(m) = game.board.parse_uci_move(s) {
+        return Some(m);
+    }
+    let moves = game.board.legal_moves();
+    let san = strip_san(s).replace('0', "O");
+    moves
+        .iter()
+        .find(|m| strip_san(&game.move2label(m, &moves)) == san)
+        .copied()
+}
+
+/// skip move numbers (1. / 1... / 1.e4) & results
+fn move_token(token: &str) -> Option<&str> {
+    if matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*") {
+        return None;
+    }
+    let s = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    if s.len() < token.len() && !token[..token.len() - s.len()].ends_with('.') {
+        // not a move number - e.g. a move starting with a digit
+        return Some(token);
+    }
+    (!s.is_empty()).then_some(s)
+}
+
+impl OpeningBook {
+    /// the compiled-in book
+    pub fn builtin() -> Self {
+        OpeningBook::parse(BUILTIN).expect("valid builtin book")
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut book = OpeningBook::default();
+        let mut game = Game::with_hash(Board::default(), crate::transposition::MIN_MB);
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            game.set_position(Board::default());
+            let mut played = vec![];
+            for token in line.split_whitespace().filter_map(move_token) {
+                let (s, weight) = match token.split_once(':') {
+                    Some((s, w)) => {
+                        let w = w
+                            .parse()
+                            .map_err(|_| format!("line {}: bad weight in '{token}'", i + 1))?;
+                        (s, w)
+                    }
+                    None => (token, 1),
+                };
+                let Some(m) = parse_move(&mut game, s) else {
+                    let moves = game.board.legal_moves();
+                    let legal: Vec<String> =
+                        moves.iter().map(|m| game.board.uci_label(m)).collect();
+                    return Err(format!(
+                        "line {}: '{s}' is not a legal move after '{}' - legal moves: {}",
+                        i + 1,
+                        played.join(" "),
+                        legal.join(" ")
+                    ));
+                };
+                book.add(game.board.hash, &m, weight);
+                played.push(s);
+                game.make_move(m);
+            }
+        }
+        Ok(book)
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        OpeningBook::parse(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// load several books - merged into one
+    pub fn load_all(paths: &[String]) -> Result<Self, String> {
+        let mut book = OpeningBook::default();
+        for path in paths {
+            book.merge(OpeningBook::load(path)?);
+        }
+        Ok(book)
+    }
+
+    pub fn merge(&mut self, other: OpeningBook) {
+        for (hash, moves) in other.positions {
+            for bm in moves {
+                self.add_data(hash, bm.data, bm.weight);
+            }
+        }
+    }
+
+    /// add weight to the move in the position with hash
+    pub fn add(&mut self, hash: u64, m: &Move, weight: u32) {
+        self.add_data(hash, m.data, weight);
+    }
+
+    fn add_data(&mut self, hash: u64, data: u16, weight: u32) {
+        let moves = self.positions.entry(hash).or_default();
+        match moves.iter_mut().find(|bm| bm.data == data) {
+            Some(bm) => bm.weight = bm.weight.saturating_add(weight),
+            None => moves.push(BookMove { data, weight }),
+        }
+    }
+
+    /// book moves in the position with hash
+    pub fn moves(&self, hash: u64) -> &[BookMove] {
+        self.positions.get(&hash).map_or(&[], |v| v.as_slice())
+    }
+
+    /// number of positions
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// legal book moves with their weights
+    pub fn candidates(&self, board: &mut Board) -> Vec<(Move, u32)> {
+        let legal = board.legal_moves();
+        self.moves(board.hash)
+            .iter()
+            .filter_map(|bm| {
+                let m = legal.iter().find(|m| m.data == bm.data)?;
+                Some((*m, bm.weight))
+            })
+            .collect()
+    }
+
+    /// random legal book move - picked with probability proportional to its weight
+    pub fn pick(&self, board: &mut Board) -> Option<Move> {
+        let moves = self.candidates(board);
+        pick_weighted(moves.iter().map(|&(m, w)| (m, w as f64)), MAX_VARIETY)
+    }
+}
+
+pub const MAX_VARIETY: u8 = 100;
+
+/// random move - variety 0 always picks the highest weight, MAX_VARIETY picks with
+/// probability proportional to the weight, values in between favour high weights
+pub fn pick_weighted(moves: impl Iterator<Item = (Move, f64)>, variety: u8) -> Option<Move> {
+    let moves: Vec<(Move, f64)> = moves.filter(|&(_, w)| w > 0.0).collect();
+    let max = moves.iter().map(|&(_, w)| w).fold(0.0, f64::max);
+    if moves.is_empty() {
+        return None;
+    } else if variety == 0 {
+        return moves.iter().find(|&&(_, w)| w == max).map(|&(m, _)| m);
+    }
+    let exponent = MAX_VARIETY as f64 / variety.min(MAX_VARIETY) as f64;
+    let weights: Vec<f64> = moves
+        .iter()
+        .map(|&(_, w)| (w / max).powf(exponent))
+        .collect();
+    let mut r = random::<f64>() * weights.iter().sum::<f64>();
+    for (&(m, _), w) in moves.iter().zip(weights) {
+        if r < w {
+            return Some(m);
+        }
+        r -= w;
+    }
+    moves.last().map(|&(m, _)| m)
+}
+
+/// opening book used by main & uci
+pub enum Book {
+    Openings(OpeningBook),
+    Polyglot(PolyglotBook),
+}
+
+impl Default for Book {
+    fn default() -> Self {
+        Book::Openings(OpeningBook::builtin())
+    }
+}
+
+impl Book {
+    /// a Polyglot book (.bin) - optionally with other Random64 keys - or text books merged
+    /// into one
+    pub fn load(paths: &[String], polyglot_keys: Option<&str>) -> Result<Book, String> {
+        match paths {
+            [path] if path.ends_with(".bin") => {
+                let keys = match polyglot_keys {
+                    Some(keys) => Keys::load(keys)?,
+                    None => Keys::default(),
+                };
+                Ok(Book::Polyglot(PolyglotBook::load(path, keys)?))
+            }
+            _ if paths.iter().any(|p| p.ends_with(".bin")) => {
+                Err("Polyglot books can not be merged".to_string())
+            }
+            _ => Ok(Book::Openings(OpeningBook::load_all(paths)?)),
+        }
+    }
+
+    /// legal book moves with their weights
+    pub fn candidates(&self, board: &mut Board) -> Vec<(Move, u32)> {
+        match self {
+            Book::Openings(b) => b.candidates(board),
+            Book::Polyglot(b) => b
+                .moves(board)
+                .into_iter()
+                .map(|(m, w)| (m, w as u32))
+                .collect(),
+        }
+    }
+
+    pub fn pick(&self, board: &mut Board) -> Option<Move> {
+        match self {
+            Book::Openings(b) => b.pick(board),
+            Book::Polyglot(b) => b.pick(board),
+        }
+    }
+
+    /// number of positions (text books) or entries (Polyglot)
+    pub fn len(&self) -> usize {
+        match self {
+            Book::Openings(b) => b.len(),
+            Book::Polyglot(b) => b.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// weight factor per game won - and divisor per game lost
+const LEARN_FACTOR: f64 = 1.5;
+const MAX_LEARNED: i32 = 10;
+
+/// results of book moves played by the engine - kept in a sidecar text file with
+/// lines "score move fen": score is wins - losses for the side making the move
+#[derive(Debug, Default, Clone)]
+pub struct BookLearning {
+    scores: HashMap<(u64, u16), (i32, String, String)>, // score, move, fen
+}
+
+impl BookLearning {
+    /// a missing file is an empty table
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut learning = BookLearning::default();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(learning),
+            Err(e) => return Err(format!("{path}: {e}")),
+        };
+        for (i, line) in text.lines().enumerate() {
+            let err = |m: &str| format!("{path}: line {}: {m}", i + 1);
+            let mut parts = line.splitn(3, ' ');
+            let (Some(score), Some(mv), Some(fen)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(err("expected 'score move fen'"));
+            };
+            let score: i32 = score.parse().map_err(|_| err("bad score"))?;
+            let board = Board::from_fen(fen).map_err(|e| err(&e))?;
+            let Some(m) = board
+                .legal_moves()
+                .into_iter()
+                .find(|m| board.uci_label(m) == mv)
+            else {
+                return Err(err(&format!("'{mv}' is not a legal move")));
+            };
+            learning.scores.insert(
+                (board.hash, m.data),
+                (score, mv.to_string(), fen.to_string()),
+            );
+        }
+        Ok(learning)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut lines: Vec<String> = self
+            .scores
+            .values()
+            .map(|(score, mv, fen)| format!("{score} {mv} {fen}"))
+            .collect();
+        lines.sort();
+        let text: String = lines.iter().map(|l| format!("{l}\n")).collect();
+        std::fs::write(path, text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// result: 1 win, 0 draw, -1 loss - for the side making the move
+    pub fn learn(&mut self, board: &Board, m: &Move, result: i32) {
+        let e = self
+            .scores
+            .entry((board.hash, m.data))
+            .or_insert_with(|| (0, board.uci_label(m), board.to_fen()));
+        e.0 = (e.0 + result).clamp(-MAX_LEARNED, MAX_LEARNED);
+    }
+
+    /// weight multiplier for the move
+    pub fn factor(&self, board: &Board, m: &Move) -> f64 {
+        self.scores
+            .get(&(board.hash, m.data))
+            .map_or(1.0, |e| LEARN_FACTOR.powi(e.0))
+    }
+}
+
+/// opening book with learning - picks moves for main & uci and learns from the results
+pub struct Library {
+    pub book: Book,
+    pub learning: BookLearning,
+    pub learn_path: Option<String>, // sidecar learning file
+    pub variety: u8,                // see pick_weighted
+    played: Vec<(String, Move)>,    // book moves played in this game: fen, move
+}
+
+impl Library {
+    pub fn new(book: Book) -> Self {
+        Library {
+            book,
+            learning: BookLearning::default(),
+            learn_path: None,
+            variety: MAX_VARIETY,
+            played: vec![],
+        }
+    }
+
+    /// load learned results from a sidecar file - saved again by game_over
+    pub fn learn_file(mut self, path: &str) -> Result<Self, String> {
+        self.learning = BookLearning::load(path)?;
+        self.learn_path = Some(path.to_string());
+        Ok(self)
+    }
+
+    pub fn variety(mut self, variety: u8) -> Self {
+        self.variety = variety.min(MAX_VARIETY);
+        self
+    }
+
+    /// book move - weights adjusted by learning
+    pub fn pick(&mut self, board: &mut Board) -> Option<Move> {
+        let moves = self.book.candidates(board);
+        let weighted = moves
+            .iter()
+            .map(|&(m, w)| (m, w as f64 * self.learning.factor(board, &m)));
+        let m = pick_weighted(weighted, self.variety)?;
+        self.played.push((board.to_fen(), m));
+        Some(m)
+    }
+
+    /// forget the book moves of an unfinished game
+    pub fn new_game(&mut self) {
+        self.played.clear();
+    }
+
+    /// learn from the book moves played - white_result: 1 white won, 0 draw, -1 black won
+    pub fn game_over(&mut self, white_result: i32) -> Result<(), String> {
+        for (fen, m) in self.played.drain(..) {
+            let board = Board::from_fen(&fen)?;
+            let result = if board.turn.is_white() {
+                white_result
+            } else {
+                -white_result
+            };
+            self.learning.learn(&board, &m, result);
+        }
+        match &self.learn_path {
+            Some(path) => self.learning.save(path),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MoveStats {
+    games: u32,
+    points: u32,     // half points scored by the side making the move
+    path: Vec<Move>, // first line seen reaching the position
+}
+
+/// (position, move) statistics from played games - for building weighted books
+#[derive(Debug, Default, Clone)]
+pub struct BookStats {
+    pub depth: usize, // max ply counted
+    stats: HashMap<(u64, u16), MoveStats>,
+}
+
+impl BookStats {
+    pub fn new(depth: usize) -> Self {
+        BookStats {
+            depth,
+            stats: HashMap::new(),
+        }
+    }
+
+    /// count the first depth moves of a game from the start position -
+    /// white_points: half points for white (2 win, 1 draw, 0 loss)
+    pub fn add_game(&mut self, moves: &[Move], white_points: u32) {
+        let mut board = Board::default();
+        for (ply, m) in moves.iter().take(self.depth).enumerate() {
+            let points = if ply % 2 == 0 {
+                white_points
+            } else {
+                2 - white_points
+            };
+            let e = self
+                .stats
+                .entry((board.hash, m.data))
+                .or_insert_with(|| MoveStats {
+                    games: 0,
+                    points: 0,
+                    path: moves[..ply].to_vec(),
+                });
+            e.games += 1;
+            e.points += points;
+            board.update(m);
+        }
+    }
+
+    /// moves played at least min_count times, with their score percentage as weight -
+    /// sorted by line
+    fn entries(&self, min_count: u32) -> Vec<(&[Move], Move, u32)> {
+        let mut l: Vec<_> = self
+            .stats
+            .iter()
+            .filter(|(_, e)| e.games >= min_count)
+            .map(|(&(_, data), e)| {
+                let weight = 50 * e.points / e.games;
+                (e.path.as_slice(), Move { data, val: 0 }, weight)
+            })
+            .collect();
+        l.sort_by_key(|(path, m, _)| (path.iter().map(|m| m.data).collect::<Vec<_>>(), m.data));
+        l
+    }
+
+    /// text book - one line per move, the moves leading to it with weight 0
+    pub fn to_text(&self, min_count: u32) -> String {
+        let mut s = String::new();
+        for (path, m, weight) in self.entries(min_count) {
+            let mut board = Board::default();
+            for pm in path {
+                s.push_str(&format!("{}:0 ", board.uci_label(pm)));
+                board.update(pm);
+            }
+            s.push_str(&format!("{}:{weight}\n", board.uci_label(&m)));
+        }
+        s
+    }
+
+    pub fn to_polyglot(&self, keys: Keys, min_count: u32) -> PolyglotBook {
+        let mut book = PolyglotBook::new(keys);
+        for (path, m, weight) in self.entries(min_count) {
+            let mut board = Board::default();
+            for pm in path {
+                board.update(pm);
+            }
+            book.add(&board, &m, weight as u16);
+        }
+        book
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::val::ROOT_FEN;
+
+    #[test]
+    fn test_builtin() {
+        let book = OpeningBook::builtin();
+        let mut board = Board::from_fen(ROOT_FEN).unwrap();
+        let moves: Vec<u32> = book.moves(board.hash).iter().map(|bm| bm.weight).collect();
+        assert_eq!(moves, vec![8, 3]); // e2e4 & d2d4 lines
+        for _ in 0..10 {
+            let m = book.pick(&mut board).unwrap();
+            assert!(["e2e4", "d2d4"].contains(&m.to_string().as_str()));
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        // UCI & PGN lines reach the same positions - weights are added
+        let book = OpeningBook::parse(
+            "e2e4 e7e5 g1f3:3 # comment\n1. e4 e5 2. Nf3+ Nc6 3.Bb5 a6 4. O-O 1-0\n",
+        )
+        .unwrap();
+        let mut game = Game::with_hash(Board::default(), 1);
+        let e4 = parse_move(&mut game, "e2e4").unwrap();
+        let expected = BookMove {
+            data: e4.data,
+            weight: 2,
+        };
+        assert_eq!(book.moves(game.board.hash), &[expected]);
+        game.make_move(e4);
+        let e5 = parse_move(&mut game, "e5").unwrap();
+        game.make_move(e5);
+        let nf3 = parse_move(&mut game, "Nf3").unwrap();
+        let expected = BookMove {
+            data: nf3.data,
+            weight: 4,
+        };
+        assert_eq!(book.moves(game.board.hash), &[expected]);
+
+        let mut other = OpeningBook::parse("e2e4:10 c7c5").unwrap();
+        other.merge(book);
+        assert_eq!(other.moves(Board::default().hash)[0].weight, 12);
+        assert_eq!(other.len(), 7);
+
+        let err = OpeningBook::parse("e2e4\ne2e4 e7e5 e4e5").unwrap_err();
+        assert!(err.starts_with("line 2: 'e4e5' is not a legal move after 'e2e4 e7e5'"));
+        assert!(OpeningBook::parse("e2e4:x").is_err());
+
+        // Chess960 castling is king-takes-rook, distinct from the king step
+        let board = Board::from_fen("5k2/8/8/8/8/8/8/5K1R w H - 0 1").unwrap();
+        let mut game = Game::with_hash(board, 1);
+        let castle = parse_move(&mut game, "f1h1").unwrap();
+        assert!(castle.data & crate::mgen::CASTLE != 0);
+        let step = parse_move(&mut game, "f1g1").unwrap();
+        assert!(step.data & crate::mgen::CASTLE == 0);
+        assert_eq!(game.board.uci_label(&castle), "f1h1");
+    }
+
+    #[test]
+    fn test_book_stats() {
+        let mut game = Game::with_hash(Board::default(), 1);
+        let mut line = |moves: &str| -> Vec<Move> {
+            game.set_position(Board::default());
+            moves
+                .split_whitespace()
+                .map(|s| {
+                    let m = parse_move(&mut game, s).unwrap();
+                    game.make_move(m);
+                    m
+                })
+                .collect()
+        };
+        let (g1, g2, g3) = (line("e4 e5 Nf3"), line("e4 c5 Nf3"), line("d4 d5"));
+        let mut stats = BookStats::new(2);
+        stats.add_game(&g1, 2); // 1-0
+        stats.add_game(&g2, 1); // draw
+        stats.add_game(&g2, 0); // 0-1
+        stats.add_game(&g3, 2);
+
+        // e4: 3 games, 1.5 points; c5: 2 games, 1.5 points - Nf3 is beyond depth 2
+        let book = OpeningBook::parse(&stats.to_text(2)).unwrap();
+        let mut board = Board::default();
+        assert_eq!(
+            book.moves(board.hash),
+            &[BookMove {
+                data: g1[0].data,
+                weight: 50
+            }]
+        );
+        board.update(&g1[0]);
+        assert_eq!(
+            book.moves(board.hash),
+            &[BookMove {
+                data: g2[1].data,
+                weight: 75
+            }]
+        );
+        assert_eq!(book.len(), 2);
+        assert_eq!(OpeningBook::parse(&stats.to_text(1)).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_learning() {
+        let book = OpeningBook::parse("e2e4:1 e7e5\nd2d4:1").unwrap();
+        let mut library = Library::new(Book::Openings(book)).variety(0);
+        let mut board = Board::default();
+        // variety 0 - equal weights, the first move
+        let e4 = library.pick(&mut board).unwrap();
+        assert_eq!(e4.to_string(), "e2e4");
+        library.game_over(-1).unwrap();
+        assert_eq!(library.learning.factor(&board, &e4), 1.0 / LEARN_FACTOR);
+        let d4 = library.pick(&mut board).unwrap();
+        assert_eq!(d4.to_string(), "d2d4");
+
+        // black learns too - and the results survive a save & load
+        board.update(&e4);
+        let e5 = library.pick(&mut board).unwrap();
+        library.game_over(-1).unwrap();
+        assert_eq!(library.learning.factor(&board, &e5), LEARN_FACTOR);
+        let path = std::env::temp_dir().join("mateus_test.learn");
+        let path = path.to_str().unwrap();
+        library.learning.save(path).unwrap();
+        let learning = BookLearning::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(learning.len(), 3);
+        assert_eq!(learning.factor(&board, &e5), LEARN_FACTOR);
+        board.backdate(&e4);
+        assert_eq!(learning.factor(&board, &d4), LEARN_FACTOR.powi(-1));
+        assert!(BookLearning::load("no_such_file.learn").unwrap().is_empty());
     }
 }
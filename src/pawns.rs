@@ -0,0 +1,86 @@
+// Pawn structure table - caches the pawn structure evaluation, indexed by
+// Board::pawn_hash. The pawn structure changes rarely during search, so most
+// evaluations find their entry here.
+// Scores depend on the playing style - clear the table when the style changes.
+
+use crate::mgen::Board;
+use crate::val::{BLACK, WHITE};
+
+const N_INDEX_BITS: usize = 16;
+const TABLE_SIZE: usize = 1 << N_INDEX_BITS;
+const MASK: usize = TABLE_SIZE - 1;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct PawnInfo {
+    pub score: i16,        // see Board::score_pawn_structure
+    pub attacks: [u64; 2], // squares attacked by white & black pawns
+    pub passed: u64,       // see Board::passed_pawns
+}
+
+impl PawnInfo {
+    pub fn new(board: &Board) -> Self {
+        PawnInfo {
+            score: board.score_pawn_structure(),
+            attacks: [board.pawn_attacks(WHITE), board.pawn_attacks(BLACK)],
+            passed: board.passed_pawns(),
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone)]
+struct PEntry {
+    key: u64,
+    used: bool,
+    info: PawnInfo,
+}
+
+pub struct PawnTable(Box<[PEntry]>);
+
+impl Default for PawnTable {
+    fn default() -> Self {
+        PawnTable(vec![PEntry::default(); TABLE_SIZE].into_boxed_slice())
+    }
+}
+
+impl PawnTable {
+    /// cached pawn structure of the board - computed on a miss
+    pub fn get(&mut self, board: &Board) -> PawnInfo {
+        let e = &mut self.0[board.pawn_hash as usize & MASK];
+        if !e.used || e.key != board.pawn_hash {
+            *e = PEntry {
+                key: board.pawn_hash,
+                used: true,
+                info: PawnInfo::new(board),
+            };
+        }
+        e.info
+    }
+
+    pub fn clear(&mut self) {
+        self.0.fill(PEntry::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmark::BRATKO_KOPEC;
+    use crate::mgen::calc_pawn_hash;
+
+    #[test]
+    fn test_pawn_table() {
+        let mut table = PawnTable::default();
+        for (fen, _) in BRATKO_KOPEC {
+            let mut board = Board::from_fen(fen).unwrap();
+            assert_eq!(table.get(&board), PawnInfo::new(&board));
+            // incremental key follows the moves
+            for m in board.legal_moves() {
+                board.update(&m);
+                assert_eq!(board.pawn_hash, calc_pawn_hash(&board.squares));
+                assert_eq!(table.get(&board), PawnInfo::new(&board));
+                board.backdate(&m);
+            }
+            assert_eq!(board.pawn_hash, calc_pawn_hash(&board.squares));
+        }
+    }
+}
@@ -0,0 +1,378 @@
+// Polyglot opening books - http://hgm.nubati.net/book_format.html
+// A .bin book is a list of 16 byte big endian entries, sorted by key:
+//   key: u64, move: u16, weight: u16, learn: u32
+// The key is the Polyglot Zobrist key of the position - independent of Board::hash.
+// It is computed from the 781 "Random64" numbers published with the format - compiled in
+// as polyglot_keys_generated.rs by bin/build_polyglot_keys. Keys::load reads another
+// table from a text file.
+// The learn field is ignored - it is kept as is when a book is written back.
+
+use crate::mgen::{Board, Move};
+use crate::polyglot_keys_generated::RANDOM64;
+use crate::val::{BISHOP, KING, KNIGHT, PAWN, Piece, QUEEN, ROOK};
+use rand::random;
+
+pub const N_KEYS: usize = 781;
+const CASTLE_OFFSET: usize = 768;
+const EN_PASSANT_OFFSET: usize = 772;
+const TURN_OFFSET: usize = 780;
+const ENTRY_BYTES: usize = 16;
+
+/// Random64 table - piece/square keys, castling, en passant file and side to move
+pub struct Keys(Box<[u64; N_KEYS]>);
+
+/// the published Random64 table
+impl Default for Keys {
+    fn default() -> Self {
+        Keys(Box::new(RANDOM64))
+    }
+}
+
+impl Keys {
+    /// all 0x.. hex literals in the text, in order - exactly N_KEYS expected
+    pub fn parse(text: &str) -> Result<Keys, String> {
+        let mut keys = vec![];
+        for token in text.split(|c: char| !c.is_ascii_alphanumeric()) {
+            if let Some(hex) = token.strip_prefix("0x").or(token.strip_prefix("0X")) {
+                let hex = hex.trim_end_matches(['u', 'U', 'l', 'L']);
+                let k = u64::from_str_radix(hex, 16)
+                    .map_err(|e| format!("Invalid key {token}: {e}"))?;
+                keys.push(k);
+            }
+        }
+        let n = keys.len();
+        let keys: Box<[u64; N_KEYS]> = keys
+            .into_boxed_slice()
+            .try_into()
+            .map_err(|_| format!("Expected {N_KEYS} Polyglot keys, found {n}"))?;
+        Ok(Keys(keys))
+    }
+
+    pub fn load(path: &str) -> Result<Keys, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        Keys::parse(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn values(&self) -> &[u64; N_KEYS] {
+        &self.0
+    }
+
+    /// Polyglot key of the position
+    pub fn key(&self, board: &Board) -> u64 {
+        let mut key = 0;
+        for (i, &p) in board.squares.iter().enumerate() {
+            if let Some(kind) = piece_index(p) {
+                let (file, row) = file_row(i as u8);
+                key ^= self.0[64 * kind + 8 * row + file];
+            }
+        }
+        // white short, long, black short, long - same order as Board::can_castle
+        for i in 0..4 {
+            if board.can_castle & 1 << i != 0 {
+                key ^= self.0[CASTLE_OFFSET + i];
+            }
+        }
+        if let Some(sq) = board.en_passant_capture_sq() {
+            key ^= self.0[EN_PASSANT_OFFSET + file_row(sq).0];
+        }
+        if board.turn.is_white() {
+            key ^= self.0[TURN_OFFSET];
+        }
+        key
+    }
+}
+
+/// Polyglot piece order: black pawn, white pawn, black knight, ... white king
+fn piece_index(p: Piece) -> Option<usize> {
+    let kind = match p.kind() {
+        PAWN => 0,
+        KNIGHT => 1,
+        BISHOP => 2,
+        ROOK => 3,
+        QUEEN => 4,
+        KING => 5,
+        _ => return None,
+    };
+    Some(2 * kind + p.is_white() as usize)
+}
+
+/// (file, row) counted from a1 - board squares are counted from h1
+fn file_row(sq: u8) -> (usize, usize) {
+    (7 - sq as usize / 8, sq as usize % 8)
+}
+
+const PROMOTIONS: [u8; 4] = [KNIGHT, BISHOP, ROOK, QUEEN];
+
+/// Polyglot move encoding - castling is encoded as king takes rook, e.g. e1h1
+pub fn encode_move(board: &Board, m: &Move) -> u16 {
+    let to = board.castle_rook(m).map_or(m.to(), |(rook, _)| rook);
+    let (ff, fr) = file_row(m.frm());
+    let (tf, tr) = file_row(to);
+    let promote = PROMOTIONS
+        .iter()
+        .position(|&k| k == m.promote_kind())
+        .map_or(0, |i| i + 1);
+    (tf | tr << 3 | ff << 6 | fr << 9 | promote << 12) as u16
+}
+
+/// the legal move matching a Polyglot move - None if there is no such move
+pub fn decode_move(board: &mut Board, data: u16) -> Option<Move> {
+    board
+        .legal_moves()
+        .into_iter()
+        .find(|m| encode_move(board, m) == data)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Entry {
+    pub key: u64,
+    pub mv: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+pub struct PolyglotBook {
+    pub keys: Keys,
+    entries: Vec<Entry>, // sorted by key
+}
+
+impl PolyglotBook {
+    pub fn new(keys: Keys) -> Self {
+        PolyglotBook {
+            keys,
+            entries: vec![],
+        }
+    }
+
+    pub fn load(path: &str, keys: Keys) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        if bytes.len() % ENTRY_BYTES != 0 {
+            return Err(format!(
+                "{path}: size {} is not a multiple of {ENTRY_BYTES} bytes",
+                bytes.len()
+            ));
+        }
+        let mut entries: Vec<Entry> = bytes
+            .chunks_exact(ENTRY_BYTES)
+            .map(|b| Entry {
+                key: u64::from_be_bytes(b[0..8].try_into().unwrap()),
+                mv: u16::from_be_bytes(b[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(b[10..12].try_into().unwrap()),
+                learn: u32::from_be_bytes(b[12..16].try_into().unwrap()),
+            })
+            .collect();
+        // books should be sorted - but lookups depend on it
+        entries.sort_by_key(|e| e.key);
+        Ok(PolyglotBook { keys, entries })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut bytes = Vec::with_capacity(self.entries.len() * ENTRY_BYTES);
+        for e in &self.entries {
+            bytes.extend(e.key.to_be_bytes());
+            bytes.extend(e.mv.to_be_bytes());
+            bytes.extend(e.weight.to_be_bytes());
+            bytes.extend(e.learn.to_be_bytes());
+        }
+        std::fs::write(path, bytes).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn entries(&self, key: u64) -> &[Entry] {
+        let i = self.entries.partition_point(|e| e.key < key);
+        let n = self.entries[i..].partition_point(|e| e.key == key);
+        &self.entries[i..i + n]
+    }
+
+    /// book moves with their weights - entries that are not legal moves are skipped
+    pub fn moves(&self, board: &mut Board) -> Vec<(Move, u16)> {
+        self.entries(self.keys.key(board))
+            .iter()
+            .filter_map(|e| decode_move(board, e.mv).map(|m| (m, e.weight)))
+            .collect()
+    }
+
+    /// random book move - picked with probability proportional to its weight
+    pub fn pick(&self, board: &mut Board) -> Option<Move> {
+        let moves = self.moves(board);
+        let total: u32 = moves.iter().map(|&(_, w)| w as u32).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut r = random::<u32>() % total;
+        for (m, w) in moves {
+            if r < w as u32 {
+                return Some(m);
+            }
+            r -= w as u32;
+        }
+        None
+    }
+
+    /// add weight to a move - e.g. from game statistics
+    pub fn add(&mut self, board: &Board, m: &Move, weight: u16) {
+        let key = self.keys.key(board);
+        let mv = encode_move(board, m);
+        let i = self.entries.partition_point(|e| e.key < key);
+        let n = self.entries[i..].partition_point(|e| e.key == key);
+        if let Some(e) = self.entries[i..i + n].iter_mut().find(|e| e.mv == mv) {
+            e.weight = e.weight.saturating_add(weight);
+        } else {
+            self.entries.insert(
+                i + n,
+                Entry {
+                    key,
+                    mv,
+                    weight,
+                    learn: 0,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmark::BRATKO_KOPEC;
+    use crate::val::ROOT_FEN;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn test_keys() -> Keys {
+        let mut rng = StdRng::seed_from_u64(11);
+        let text: Vec<String> = (0..N_KEYS)
+            .map(|_| format!("0x{:016X}ULL", rng.random::<u64>()))
+            .collect();
+        Keys::parse(&text.join(",\n")).unwrap()
+    }
+
+    fn find(board: &mut Board, s: &str) -> Move {
+        board
+            .legal_moves()
+            .into_iter()
+            .find(|m| m.to_string() == s)
+            .unwrap()
+    }
+
+    fn play(board: &mut Board, moves: &[&str]) {
+        for s in moves {
+            let m = find(board, s);
+            board.update(&m);
+        }
+    }
+
+    #[test]
+    fn test_keys_parse() {
+        assert!(Keys::parse("0x1, 0x2").is_err());
+        assert!(Keys::parse(&"0xZZ ".repeat(N_KEYS)).is_err());
+        let keys = test_keys();
+        let board = Board::from_fen(ROOT_FEN).unwrap();
+        assert_ne!(keys.key(&board), 0);
+    }
+
+    #[test]
+    fn test_key() {
+        let keys = test_keys();
+        // same position, different move order
+        let mut b1 = Board::from_fen(ROOT_FEN).unwrap();
+        let mut b2 = Board::from_fen(ROOT_FEN).unwrap();
+        play(&mut b1, &["g1f3", "g8f6", "b1c3"]);
+        play(&mut b2, &["b1c3", "g8f6", "g1f3"]);
+        assert_eq!(keys.key(&b1), keys.key(&b2));
+
+        // en passant only counts if it can be captured
+        let fen = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3";
+        let b1 = Board::from_fen(fen).unwrap();
+        let b2 = Board::from_fen(&fen.replace("e3", "-")).unwrap();
+        assert_ne!(keys.key(&b1), keys.key(&b2));
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        let b1 = Board::from_fen(fen).unwrap();
+        let b2 = Board::from_fen(&fen.replace("e3", "-")).unwrap();
+        assert_eq!(keys.key(&b1), keys.key(&b2));
+
+        // castling rights
+        let b2 = Board::from_fen(&fen.replace("KQkq", "Qkq")).unwrap();
+        assert_ne!(keys.key(&b1), keys.key(&b2));
+    }
+
+    #[test]
+    fn test_move_encoding() {
+        let mut board = Board::from_fen(ROOT_FEN).unwrap();
+        let m = find(&mut board, "e2e4");
+        // e2e4: from file 4, row 1, to file 4, row 3
+        assert_eq!(encode_move(&board, &m), 4 | 3 << 3 | 4 << 6 | 1 << 9);
+
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        let mut board = Board::from_fen(fen).unwrap();
+        let m = find(&mut board, "e1g1");
+        let data = encode_move(&board, &m);
+        assert_eq!(data, 7 | 4 << 6); // e1h1
+        assert_eq!(decode_move(&mut board, data), Some(m));
+
+        for (fen, _) in BRATKO_KOPEC {
+            let mut board = Board::from_fen(fen).unwrap();
+            for m in board.legal_moves() {
+                let data = encode_move(&board, &m);
+                assert_eq!(decode_move(&mut board, data), Some(m), "{fen} {m}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut book = PolyglotBook::new(test_keys());
+        let mut board = Board::from_fen(ROOT_FEN).unwrap();
+        let moves = board.legal_moves();
+        book.add(&board, &moves[3], 10);
+        book.add(&board, &moves[3], 5);
+        book.add(&board, &moves[7], 0);
+        play(&mut board, &["d2d4"]);
+        let reply = board.legal_moves()[0];
+        book.add(&board, &reply, 1);
+        assert_eq!(book.len(), 3);
+
+        let path = std::env::temp_dir().join("mateus_test.bin");
+        let path = path.to_str().unwrap();
+        book.save(path).unwrap();
+        let book = PolyglotBook::load(path, test_keys()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(book.moves(&mut board), vec![(reply, 1)]);
+        let mut board = Board::from_fen(ROOT_FEN).unwrap();
+        assert_eq!(book.moves(&mut board), vec![(moves[3], 15), (moves[7], 0)]);
+        // weight 0 is never picked
+        for _ in 0..10 {
+            assert_eq!(book.pick(&mut board), Some(moves[3]));
+        }
+    }
+
+    /// keys listed in the format description
+    #[test]
+    fn test_reference_keys() {
+        let keys = Keys::default();
+        let cases = [
+            ("", 0x463b96181691fc9c),
+            ("e2e4", 0x823c9b50fd114196),
+            ("e2e4 d7d5", 0x0756b94461c50fb0),
+            ("e2e4 d7d5 e4e5", 0x662fafb965db29d4),
+            ("e2e4 d7d5 e4e5 f7f5", 0x22a48b5a8e47ff78),
+            ("e2e4 d7d5 e4e5 f7f5 e1e2", 0x652a607ca3f242c1),
+            ("e2e4 d7d5 e4e5 f7f5 e1e2 e8f7", 0x00fdd303c946bdd9),
+            ("a2a4 b7b5 h2h4 b5b4 c2c4", 0x3c8123ea7b067637),
+            ("a2a4 b7b5 h2h4 b5b4 c2c4 b4c3 a1a3", 0x5c3f9b829b279560),
+        ];
+        for (moves, key) in cases {
+            let mut board = Board::from_fen(ROOT_FEN).unwrap();
+            play(&mut board, &moves.split_whitespace().collect::<Vec<_>>());
+            assert_eq!(keys.key(&board), key, "{moves}");
+        }
+    }
+}
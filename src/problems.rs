@@ -0,0 +1,257 @@
+// Chess problem solver - orthodox mates, helpmates and selfmates
+// https://en.wikipedia.org/wiki/Chess_problem
+//
+// All solutions up to n moves are enumerated by exhaustive search, so that
+// cooks (unintended extra solutions) are detected as well.
+
+use crate::Game;
+use crate::mgen::Move;
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stipulation {
+    /// #n - side to move mates in n moves against any defence
+    Mate,
+    /// h#n - side to move starts, both sides cooperate so that the opponent mates on its n'th move
+    Helpmate,
+    /// s#n - side to move forces the opponent to give mate within n moves
+    Selfmate,
+}
+
+impl Stipulation {
+    /// parse problem notation, e.g. "#2", "h#3" or "s#2"
+    pub fn parse(s: &str) -> Option<(Stipulation, usize)> {
+        let (stipulation, n) = if let Some(n) = s.strip_prefix("h#") {
+            (Stipulation::Helpmate, n)
+        } else if let Some(n) = s.strip_prefix("s#") {
+            (Stipulation::Selfmate, n)
+        } else if let Some(n) = s.strip_prefix('#') {
+            (Stipulation::Mate, n)
+        } else {
+            return None;
+        };
+        match n.parse::<usize>() {
+            Ok(n) if n > 0 => Some((stipulation, n)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Stipulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Stipulation::Mate => "#",
+            Stipulation::Helpmate => "h#",
+            Stipulation::Selfmate => "s#",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution {
+    /// number of moves needed
+    pub n: usize,
+    /// key move - or the full line for helpmates
+    pub moves: Vec<Move>,
+    /// moves in SAN
+    pub labels: Vec<String>,
+}
+
+impl fmt::Display for Solution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.labels.join(" "))
+    }
+}
+
+/// More than one solution means the problem is cooked
+pub fn is_cooked(solutions: &[Solution]) -> bool {
+    solutions.len() > 1
+}
+
+/// Find all solutions in n moves or less
+pub fn solve(game: &mut Game, stipulation: Stipulation, n: usize) -> Vec<Solution> {
+    let lines = match stipulation {
+        Stipulation::Helpmate => {
+            let mut lines = vec![];
+            for k in 1..=n {
+                let mut path = vec![];
+                let mut found = vec![];
+                helpmates(game, 2 * k, &mut path, &mut found);
+                lines.extend(found.into_iter().map(|l| (k, l)));
+            }
+            lines
+        }
+        Stipulation::Mate | Stipulation::Selfmate => {
+            let test = match stipulation {
+                Stipulation::Mate => mate_defence_fails,
+                _ => selfmate_defence_fails,
+            };
+            let moves = game.board.legal_moves();
+            let mut solved = vec![false; moves.len()];
+            let mut lines = vec![];
+            for k in 1..=n {
+                for (i, m) in moves.iter().enumerate() {
+                    if solved[i] {
+                        continue;
+                    }
+                    game.board.update(m);
+                    if test(game, k) {
+                        solved[i] = true;
+                        lines.push((k, vec![*m]));
+                    }
+                    game.board.backdate(m);
+                }
+            }
+            lines
+        }
+    };
+
+    lines
+        .into_iter()
+        .map(|(k, moves)| Solution {
+            n: k,
+            labels: labels(game, &moves),
+            moves,
+        })
+        .collect()
+}
+
+/// true if side to move is checkmated
+fn is_mate(game: &mut Game) -> bool {
+    game.board.in_check(game.board.turn) && game.board.legal_moves().is_empty()
+}
+
+fn labels(game: &mut Game, line: &[Move]) -> Vec<String> {
+    let mut v = Vec::with_capacity(line.len());
+    for m in line {
+        let moves = game.board.legal_moves();
+        v.push(game.move2label(m, &moves));
+        game.board.update(m);
+    }
+    for m in line.iter().rev() {
+        game.board.backdate(m);
+    }
+    v
+}
+
+/// attacker to move - true if mate can be forced in n moves
+fn mates(game: &mut Game, n: usize) -> bool {
+    for m in game.board.legal_moves() {
+        game.board.update(&m);
+        let r = mate_defence_fails(game, n);
+        game.board.backdate(&m);
+        if r {
+            return true;
+        }
+    }
+    false
+}
+
+/// defender to move - true if every defence is mated within n moves (counting the move just made)
+fn mate_defence_fails(game: &mut Game, n: usize) -> bool {
+    let moves = game.board.legal_moves();
+    if moves.is_empty() {
+        return game.board.in_check(game.board.turn); // mate - not stalemate
+    }
+    if n == 1 {
+        return false;
+    }
+    moves.iter().all(|m| {
+        game.board.update(m);
+        let r = mates(game, n - 1);
+        game.board.backdate(m);
+        r
+    })
+}
+
+/// attacker to move - true if the defender can be forced to give mate in n moves
+fn forces_selfmate(game: &mut Game, n: usize) -> bool {
+    for m in game.board.legal_moves() {
+        game.board.update(&m);
+        let r = selfmate_defence_fails(game, n);
+        game.board.backdate(&m);
+        if r {
+            return true;
+        }
+    }
+    false
+}
+
+/// defender to move - true if every move either mates the attacker or leads to a forced selfmate
+fn selfmate_defence_fails(game: &mut Game, n: usize) -> bool {
+    let moves = game.board.legal_moves();
+    if moves.is_empty() {
+        return false; // defender mated or stalemated
+    }
+    moves.iter().all(|m| {
+        game.board.update(m);
+        let r = is_mate(game) || (n > 1 && forces_selfmate(game, n - 1));
+        game.board.backdate(m);
+        r
+    })
+}
+
+/// collect all cooperative lines of exactly plies half moves ending in mate
+fn helpmates(game: &mut Game, plies: usize, path: &mut Vec<Move>, found: &mut Vec<Vec<Move>>) {
+    for m in game.board.legal_moves() {
+        game.board.update(&m);
+        path.push(m);
+        if plies == 1 {
+            if is_mate(game) {
+                found.push(path.clone());
+            }
+        } else {
+            helpmates(game, plies - 1, path, found);
+        }
+        path.pop();
+        game.board.backdate(&m);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mgen::Board;
+
+    fn solve_fen(fen: &str, s: &str) -> Vec<Solution> {
+        let (stipulation, n) = Stipulation::parse(s).unwrap();
+        let mut game = Game::new(Board::from_fen(fen).unwrap());
+        solve(&mut game, stipulation, n)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Stipulation::parse("#2"), Some((Stipulation::Mate, 2)));
+        assert_eq!(Stipulation::parse("h#3"), Some((Stipulation::Helpmate, 3)));
+        assert_eq!(Stipulation::parse("s#1"), Some((Stipulation::Selfmate, 1)));
+        assert_eq!(Stipulation::parse("s#0"), None);
+        assert_eq!(Stipulation::parse("x#1"), None);
+    }
+
+    #[test]
+    fn test_mate() {
+        let l = solve_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "#2");
+        assert_eq!(l.len(), 1);
+        assert_eq!((l[0].n, l[0].to_string().as_str()), (1, "Ra8#"));
+    }
+
+    #[test]
+    fn test_helpmate() {
+        let l = solve_fen("7k/8/6K1/8/8/8/8/R7 b - - 0 1", "h#1");
+        assert!(!is_cooked(&l));
+        assert_eq!(l[0].to_string(), "Kg8 Ra8#");
+
+        // rook can wait on any square of the a-file
+        let l = solve_fen("7k/8/6K1/8/8/8/8/R7 b - - 0 1", "h#2");
+        assert!(is_cooked(&l));
+        assert!(l.iter().any(|s| s.to_string() == "Kg8 Ra5 Kh8 Ra8#"));
+    }
+
+    #[test]
+    fn test_selfmate() {
+        let l = solve_fen("1rkr4/1p1p4/8/8/8/8/PP6/K2Q3r w - - 0 1", "s#1");
+        assert_eq!(l.len(), 1);
+        assert_eq!(l[0].to_string(), "Qc1+");
+    }
+}
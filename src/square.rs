@@ -0,0 +1,216 @@
+// Square, File and Rank - typed coordinates for the board index. The index runs
+// up the ranks of a file, from the h-file to the a-file: h1 = 0, h8 = 7, a1 = 56,
+// a8 = 63, i.e. (7 - file) * 8 + rank. index() and from_index() convert to and
+// from the legacy u8/usize index used by Board and Move.
+
+use crate::bitmaps::Bitboard;
+use crate::val::I2SQ;
+use std::fmt;
+use std::str::FromStr;
+
+/// a-file = 0 .. h-file = 7
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct File(u8);
+
+/// 1st rank = 0 .. 8th rank = 7
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Rank(u8);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Square(u8);
+
+impl File {
+    pub const A: File = File(0);
+    pub const H: File = File(7);
+
+    pub const fn new(i: u8) -> File {
+        assert!(i < 8);
+        File(i)
+    }
+
+    pub const fn from_char(c: char) -> Option<File> {
+        match c {
+            'a'..='h' => Some(File(c as u8 - b'a')),
+            _ => None,
+        }
+    }
+
+    pub const fn index(self) -> u8 {
+        self.0
+    }
+
+    pub const fn to_char(self) -> char {
+        (b'a' + self.0) as char
+    }
+
+    /// all squares of the file
+    pub const fn bb(self) -> Bitboard {
+        Bitboard(0xFF << ((7 - self.0) * 8))
+    }
+
+    pub fn all() -> impl DoubleEndedIterator<Item = File> {
+        (0..8).map(File)
+    }
+}
+
+impl Rank {
+    pub const FIRST: Rank = Rank(0);
+    pub const EIGHTH: Rank = Rank(7);
+
+    pub const fn new(i: u8) -> Rank {
+        assert!(i < 8);
+        Rank(i)
+    }
+
+    pub const fn from_char(c: char) -> Option<Rank> {
+        match c {
+            '1'..='8' => Some(Rank(c as u8 - b'1')),
+            _ => None,
+        }
+    }
+
+    pub const fn index(self) -> u8 {
+        self.0
+    }
+
+    pub const fn to_char(self) -> char {
+        (b'1' + self.0) as char
+    }
+
+    /// all squares of the rank
+    pub const fn bb(self) -> Bitboard {
+        Bitboard(0x01010101_01010101 << self.0)
+    }
+
+    pub fn all() -> impl DoubleEndedIterator<Item = Rank> {
+        (0..8).map(Rank)
+    }
+}
+
+impl Square {
+    pub const fn new(file: File, rank: Rank) -> Square {
+        Square((7 - file.0) * 8 + rank.0)
+    }
+
+    /// from the legacy board index
+    pub const fn from_index(i: u8) -> Square {
+        assert!(i < 64);
+        Square(i)
+    }
+
+    /// the legacy board index
+    pub const fn index(self) -> u8 {
+        self.0
+    }
+
+    pub const fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+
+    pub const fn file(self) -> File {
+        File(7 - self.0 / 8)
+    }
+
+    pub const fn rank(self) -> Rank {
+        Rank(self.0 % 8)
+    }
+
+    /// "e4" => Some(e4)
+    pub const fn parse(coord: &str) -> Option<Square> {
+        let bytes = coord.as_bytes();
+        if bytes.len() != 2 {
+            return None;
+        }
+        match (
+            File::from_char(bytes[0] as char),
+            Rank::from_char(bytes[1] as char),
+        ) {
+            (Some(file), Some(rank)) => Some(Square::new(file, rank)),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        I2SQ[self.0 as usize]
+    }
+
+    pub const fn bb(self) -> Bitboard {
+        Bitboard(1 << self.0)
+    }
+
+    /// the square seen from the other side of the board, e.g. e2 => e7
+    pub const fn flip(self) -> Square {
+        Square(self.0 ^ 7)
+    }
+
+    /// h1, h2 .. a8 - in index order
+    pub fn all() -> impl DoubleEndedIterator<Item = Square> {
+        (0..64).map(Square)
+    }
+}
+
+impl From<Square> for u8 {
+    fn from(sq: Square) -> u8 {
+        sq.0
+    }
+}
+
+impl From<Square> for usize {
+    fn from(sq: Square) -> usize {
+        sq.0 as usize
+    }
+}
+
+impl FromStr for Square {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Square, String> {
+        Square::parse(s).ok_or(format!("Invalid square '{s}'"))
+    }
+}
+
+impl fmt::Display for File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_char())
+    }
+}
+
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_char())
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square() {
+        let e4 = Square::parse("e4").unwrap();
+        assert_eq!(e4.index(), 27);
+        assert_eq!((e4.file().to_char(), e4.rank().to_char()), ('e', '4'));
+        assert_eq!(Square::new(File::A, Rank::EIGHTH).index(), 63);
+        assert_eq!(e4.flip().to_string(), "e5");
+        assert_eq!("h1".parse::<Square>(), Ok(Square::from_index(0)));
+        assert!("i1".parse::<Square>().is_err() && Square::parse("e").is_none());
+        for sq in Square::all() {
+            assert_eq!(Square::new(sq.file(), sq.rank()), sq);
+            assert_eq!(sq.to_string().parse::<Square>(), Ok(sq));
+            assert!(sq.file().bb().contains(sq) && sq.rank().bb().contains(sq));
+        }
+        assert_eq!(
+            File::all()
+                .map(|f| f.bb())
+                .fold(Bitboard(0), |a, b| a | b)
+                .count(),
+            64
+        );
+    }
+}
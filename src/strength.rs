@@ -0,0 +1,126 @@
+// Strength limitation - play below full strength, e.g. as a sparring partner.
+//
+// A skill level 0-20 (or a target Elo) is mapped to a node and depth budget.
+// The best few root moves are then scored exactly (MultiPV) and one is picked at
+// random, weighted by how close its score is to the best one. Weaker levels get a
+// wider score margin and so play more inaccurate moves.
+
+use crate::SearchConstraints;
+use crate::mgen::Move;
+use rand::random;
+
+pub const MAX_SKILL: u8 = 20;
+pub const MIN_ELO: u16 = 800;
+pub const MAX_ELO: u16 = 2200;
+
+const MULTIPV: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Strength {
+    skill: u8,
+}
+
+impl Strength {
+    pub fn new(skill: u8) -> Self {
+        Strength {
+            skill: skill.min(MAX_SKILL),
+        }
+    }
+
+    /// linear map of MIN_ELO-MAX_ELO to skill 0-20
+    pub fn from_elo(elo: u16) -> Self {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        let skill = (elo - MIN_ELO) as u32 * MAX_SKILL as u32 / (MAX_ELO - MIN_ELO) as u32;
+        Strength::new(skill as u8)
+    }
+
+    pub fn skill(&self) -> u8 {
+        self.skill
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.skill < MAX_SKILL
+    }
+
+    /// node budget doubles every 2nd skill level: 2000 at level 0, ~1M at level 19
+    pub fn nodes(&self) -> usize {
+        2000 << (self.skill / 2)
+    }
+
+    /// depth limit - 2 at level 0, 8 at level 19
+    pub fn depth(&self) -> u8 {
+        2 + self.skill / 3
+    }
+
+    /// centipawns a move may be worse than the best and still get picked
+    pub fn margin(&self) -> i16 {
+        15 * (MAX_SKILL - self.skill) as i16
+    }
+
+    /// tighten search constraints to the skill level
+    pub fn constrain(&self, sc: &SearchConstraints) -> SearchConstraints {
+        if !self.is_limited() {
+            return sc.clone();
+        }
+        let nodes = sc.nodes.map_or(self.nodes(), |n| n.min(self.nodes()));
+        let depth = sc.depth.map_or(self.depth(), |d| d.min(self.depth()));
+        sc.clone().nodes(nodes).depth(depth).multipv(MULTIPV)
+    }
+
+    /// weighted random pick among the MultiPV candidates - expects scored moves sorted best first.
+    /// Returns index of the chosen move
+    pub fn pick(&self, l: &[(Move, i16)]) -> usize {
+        let Some(&(_, best)) = l.first() else {
+            return 0;
+        };
+        if !self.is_limited() {
+            return 0;
+        }
+        let weights: Vec<i32> = l
+            .iter()
+            .take(MULTIPV)
+            .map(|(_, score)| match best as i32 - *score as i32 {
+                d if d <= self.margin() as i32 => self.margin() as i32 - d + 1,
+                _ => 0,
+            })
+            .collect();
+        let mut r = (random::<u32>() % weights.iter().sum::<i32>() as u32) as i32;
+        for (i, w) in weights.iter().enumerate() {
+            if r < *w {
+                return i;
+            }
+            r -= w;
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mgen::Board;
+
+    #[test]
+    fn test_from_elo() {
+        assert_eq!(Strength::from_elo(0).skill(), 0);
+        assert_eq!(Strength::from_elo(1500).skill(), 10);
+        assert_eq!(Strength::from_elo(3000).skill(), MAX_SKILL);
+        assert!(!Strength::from_elo(MAX_ELO).is_limited());
+    }
+
+    #[test]
+    fn test_pick() {
+        let moves = Board::default().legal_moves();
+        let l: Vec<_> = moves
+            .iter()
+            .zip([50, 40, -500, -600])
+            .map(|(m, s)| (*m, s))
+            .collect();
+        let strength = Strength::new(10);
+        for _ in 0..100 {
+            let (_, score) = l[strength.pick(&l)];
+            assert!(score >= 50 - strength.margin());
+        }
+        assert_eq!(Strength::new(MAX_SKILL).pick(&l), 0);
+    }
+}
@@ -0,0 +1,99 @@
+// Playing style profiles - bias evaluation and search to give the engine a
+// recognisable personality. The default profile is the plain evaluation.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Style {
+    pub name: &'static str,
+    /// mobility weight in percent
+    pub mobility: i16,
+    /// bonus per attacked square next to the enemy king
+    pub king_attack: i16,
+    /// pawn structure penalties & passed pawn bonus (times rank squared)
+    pub doubled_pawn: i16,
+    pub isolated_pawn: i16,
+    pub passed_pawn: i16,
+    /// bonus per 1000 traded material for the side that is ahead - negative avoids exchanges
+    pub exchange: i16,
+    /// draw score penalty for the engine's side
+    pub contempt: i16,
+}
+
+pub const DEFAULT: Style = Style {
+    name: "default",
+    mobility: 100,
+    king_attack: 0,
+    doubled_pawn: 20,
+    isolated_pawn: 4,
+    passed_pawn: 2,
+    exchange: 0,
+    contempt: 0,
+};
+
+pub const AGGRESSIVE: Style = Style {
+    name: "aggressive",
+    mobility: 150,
+    king_attack: 8,
+    doubled_pawn: 15,
+    isolated_pawn: 3,
+    passed_pawn: 2,
+    exchange: -10,
+    contempt: 30,
+};
+
+pub const SOLID: Style = Style {
+    name: "solid",
+    mobility: 80,
+    king_attack: 0,
+    doubled_pawn: 30,
+    isolated_pawn: 8,
+    passed_pawn: 3,
+    exchange: 10,
+    contempt: 0,
+};
+
+pub const MATERIALISTIC: Style = Style {
+    name: "materialistic",
+    mobility: 50,
+    king_attack: 0,
+    doubled_pawn: 20,
+    isolated_pawn: 4,
+    passed_pawn: 2,
+    exchange: 25,
+    contempt: 10,
+};
+
+pub const STYLES: [Style; 4] = [DEFAULT, AGGRESSIVE, SOLID, MATERIALISTIC];
+pub const NAMES: [&str; 4] = [
+    DEFAULT.name,
+    AGGRESSIVE.name,
+    SOLID.name,
+    MATERIALISTIC.name,
+];
+
+impl Default for Style {
+    fn default() -> Self {
+        DEFAULT
+    }
+}
+
+impl Style {
+    pub fn from_name(name: &str) -> Option<Style> {
+        STYLES
+            .into_iter()
+            .find(|s| s.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        for (s, name) in STYLES.iter().zip(NAMES) {
+            assert_eq!(Style::from_name(name), Some(*s));
+        }
+        assert_eq!(Style::from_name("Aggressive"), Some(AGGRESSIVE));
+        assert_eq!(Style::from_name("reckless"), None);
+    }
+}
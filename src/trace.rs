@@ -0,0 +1,203 @@
+// Search tree trace - records the nodes visited by pvs and quiescence search
+// for debugging. Tracing is opt-in (Game::trace) and limited by ply and node count.
+// The tree can be exported in Graphviz DOT format or as JSON. Windows and scores
+// are negamax - seen from the side to move in the node.
+
+use crate::mgen::Move;
+use std::fmt::Write;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Bound {
+    Exact,
+    Lower, // fail high
+    Upper, // fail low
+}
+
+impl Bound {
+    fn new(score: i16, alpha: i16, beta: i16) -> Bound {
+        if score <= alpha {
+            Bound::Upper
+        } else if score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Bound::Exact => "exact",
+            Bound::Lower => "lower",
+            Bound::Upper => "upper",
+        }
+    }
+}
+
+/// Why the search of a node stopped
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Cutoff {
+    None,       // all moves searched
+    Beta,       // beta cut-off
+    TTable,     // transposition table hit
+    Repetition, // draw by repetition
+    Checkmate,
+    Stalemate,
+    Quiescence, // handed over to quiescence search
+    Eval,       // quiet position - static evaluation
+}
+
+impl Cutoff {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cutoff::None => "none",
+            Cutoff::Beta => "beta",
+            Cutoff::TTable => "ttable",
+            Cutoff::Repetition => "repetition",
+            Cutoff::Checkmate => "checkmate",
+            Cutoff::Stalemate => "stalemate",
+            Cutoff::Quiescence => "quiescence",
+            Cutoff::Eval => "eval",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceNode {
+    pub parent: Option<usize>,
+    pub ply: usize,
+    pub mv: Option<Move>, // None for the root
+    pub alpha: i16,
+    pub beta: i16,
+    pub score: i16,
+    pub bound: Bound,
+    pub cutoff: Cutoff,
+    pub quiescence: bool,
+}
+
+impl TraceNode {
+    fn label(&self) -> String {
+        match self.mv {
+            Some(m) => m.to_string(),
+            None => "root".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchTrace {
+    pub max_ply: usize,
+    pub max_nodes: usize,
+    pub nodes: Vec<TraceNode>,
+    stack: Vec<usize>,
+}
+
+impl SearchTrace {
+    pub fn new(max_ply: usize, max_nodes: usize) -> Self {
+        SearchTrace {
+            max_ply,
+            max_nodes,
+            nodes: vec![],
+            stack: vec![],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.stack.clear();
+    }
+
+    /// open a node - returns None if beyond the depth or node limit
+    pub fn enter(
+        &mut self,
+        mv: Option<Move>,
+        alpha: i16,
+        beta: i16,
+        quiescence: bool,
+    ) -> Option<usize> {
+        let ply = self.stack.len();
+        if ply > self.max_ply || self.nodes.len() >= self.max_nodes {
+            return None;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(TraceNode {
+            parent: self.stack.last().copied(),
+            ply,
+            mv,
+            alpha,
+            beta,
+            score: 0,
+            bound: Bound::Exact,
+            cutoff: Cutoff::None,
+            quiescence,
+        });
+        self.stack.push(id);
+        Some(id)
+    }
+
+    /// close a node opened by enter
+    pub fn leave(&mut self, id: Option<usize>, score: i16, cutoff: Cutoff) {
+        if let Some(id) = id {
+            self.stack.pop();
+            let n = &mut self.nodes[id];
+            n.score = score;
+            n.bound = Bound::new(score, n.alpha, n.beta);
+            n.cutoff = cutoff;
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut s = String::from("digraph search {\n  node [shape=box, fontname=\"monospace\"];\n");
+        for (i, n) in self.nodes.iter().enumerate() {
+            let style = if n.quiescence { ", style=dashed" } else { "" };
+            writeln!(
+                s,
+                "  n{i} [label=\"{}\\n[{}, {}]\\n{} {}\\n{}\"{style}];",
+                n.label(),
+                n.alpha,
+                n.beta,
+                n.score,
+                n.bound.name(),
+                n.cutoff.name()
+            )
+            .unwrap();
+            if let Some(p) = n.parent {
+                writeln!(s, "  n{p} -> n{i};").unwrap();
+            }
+        }
+        s.push_str("}\n");
+        s
+    }
+
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| {
+                let parent = n.parent.map_or("null".to_string(), |p| p.to_string());
+                format!(
+                    "  {{\"id\": {i}, \"parent\": {parent}, \"ply\": {}, \"move\": \"{}\", \"alpha\": {}, \"beta\": {}, \"score\": {}, \"bound\": \"{}\", \"cutoff\": \"{}\", \"quiescence\": {}}}",
+                    n.ply,
+                    n.label(),
+                    n.alpha,
+                    n.beta,
+                    n.score,
+                    n.bound.name(),
+                    n.cutoff.name(),
+                    n.quiescence
+                )
+            })
+            .collect();
+        format!("[\n{}\n]\n", nodes.join(",\n"))
+    }
+
+    /// write as JSON if path ends in .json, otherwise as DOT
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let s = if path.ends_with(".json") {
+            self.to_json()
+        } else {
+            self.to_dot()
+        };
+        std::fs::write(path, s)
+    }
+}
@@ -1,56 +1,78 @@
+use crate::hashkeys_generated::*;
 use crate::mgen::{self, Move};
 use static_assertions::const_assert;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
 
 // Ensure usize is at least 64-bit at compile time
 const_assert!(std::mem::size_of::<usize>() >= std::mem::size_of::<u64>());
 
-// Transposition table - specify table size with N_INDEX_BITS.
+// Transposition table - size is specified in MB when the table is created.
+// The table is an array of buckets, each holding BUCKET_SIZE entries (by default -
+// see with_bucket_size). The number of
+// buckets is the largest power of 2 that fits the budget, and the hash key is split
+// into a bucket index (the low bits) and a reminder (the rest).
 // Each table entry stores
 // * reminder of hash key - the part that was not used for the index
 // * search depth
 // * search score
-// * best move
+// * best move - including promotion piece and castle flag
 // * score bound - upper, lower, exact
+// * age - the search generation that stored it (0 => empty)
+//
+// Replacement: an entry for the same position is overwritten, otherwise the least
+// valuable entry in the bucket - empty, then stale (older generation), then shallow.
 //
 // Example
-// N_INDEX_BITS is 24 => TABLE_SIZE = 16M
-//                       N_REMINDER_BITS = 40
-//                       N_REMINDER_BYTES = 5
-//                       N_ENTRY_BYTES = 10
-// Entry: N_REMINDER_BYTES (5) + Depth (1) + score (2) + move data (2 bytes / 14 bits) = 10
-
-// Table size - examples
-// 2 ^  0 =          1
-// 2 ^  1 =          2
-// 2 ^  2 =          4
-// 2 ^  3 =          8
-// 2 ^  4 =         16
-// 2 ^  5 =         32
-// 2 ^  6 =         64
-// 2 ^  7 =        128
-// 2 ^  8 =        256
-// 2 ^ 20 =    1048576 =   1M
-// 2 ^ 21 =    2097152 =   2M
-// 2 ^ 22 =    4194304 =   4M
-// 2 ^ 23 =    8388608 =   8M
-// 2 ^ 24 =   16777216 =  16M
-// 2 ^ 25 =   33554432 =  34M
-// 2 ^ 26 =   67108864 =  64M
-// 2 ^ 27 =  134217728 = 128M
-// 2 ^ 28 =  268435456 = 256M
-// 2 ^ 29 =  536870912 = 512M
-// 2 ^ 30 = 1073741824 =   1G
-
-const N_INDEX_BITS: usize = 24;
-const TABLE_SIZE: usize = 1 << N_INDEX_BITS;
-const MASK: usize = TABLE_SIZE - 1;
-
-const N_REMINDER_BITS: usize = 64 - N_INDEX_BITS;
-const N_REMINDER_BYTES: usize = (N_REMINDER_BITS + 7) / 8;
-const N_ENTRY_BYTES: usize = N_REMINDER_BYTES + 5;
-
-const fn reminder_to_slice(hash_key: u64, array: &mut [u8]) {
-    let reminder = hash_key >> N_INDEX_BITS;
+// 256 MB => 4M buckets = 16M entries
+//           index bits = 22
+//           reminder bits = 42
+// Entry: N_REMINDER_BYTES (7) + Depth (1) + score (2) + move data (2) + age & bound (1) = 13
+//
+// The reminder field is wide enough for tables of 2^MIN_INDEX_BITS buckets or more.
+//
+// Saved table file: header followed by the buckets. The header records the hash key
+// scheme and the entry layout so that incompatible files are rejected.
+// Header: MAGIC (8) + key scheme (8) + entry bytes (4) + bucket size (4) + index bits (4) + generation (4)
+
+pub const DEFAULT_MB: usize = 256;
+pub const MIN_MB: usize = 1;
+pub const MAX_MB: usize = 65536;
+
+const BUCKET_SIZE: usize = 4;
+const MAX_BUCKET_SIZE: usize = 16;
+const MIN_INDEX_BITS: usize = 14;
+const N_REMINDER_BYTES: usize = (64 - MIN_INDEX_BITS).div_ceil(8);
+const N_ENTRY_BYTES: usize = N_REMINDER_BYTES + 6;
+const FLAGS: usize = N_ENTRY_BYTES - 1; // age (6 bits) & bound (2 bits)
+const AGE_MASK: u8 = 0b111111;
+const MAGIC: &[u8; 8] = b"MATEUSTT";
+
+/// fingerprint of the hash keys in hashkeys_generated.rs
+fn key_scheme() -> u64 {
+    let tables = [
+        &R1_HASH, &R2_HASH, &N1_HASH, &N2_HASH, &B1_HASH, &B2_HASH, &K1_HASH, &K2_HASH, &Q1_HASH,
+        &Q2_HASH, &P1_HASH, &P2_HASH,
+    ];
+    tables
+        .iter()
+        .flat_map(|t| t.iter())
+        .chain(CASTLE_HASH.iter())
+        .chain(EN_PASSANT_HASH.iter())
+        .chain([WHITE_HASH].iter())
+        .fold(0, |acc, &k| acc.rotate_left(7) ^ k)
+}
+
+fn header(bucket_size: usize, index_bits: usize, generation: u8) -> Vec<u8> {
+    let mut h = MAGIC.to_vec();
+    h.extend(key_scheme().to_le_bytes());
+    for x in [N_ENTRY_BYTES, bucket_size, index_bits, generation as usize] {
+        h.extend((x as u32).to_le_bytes());
+    }
+    h
+}
+
+const fn reminder_to_slice(reminder: u64, array: &mut [u8]) {
     let bytes = reminder.to_le_bytes();
 
     let mut i = 0;
@@ -104,23 +126,43 @@ impl Default for TEntry {
 
 impl TEntry {
     // Score is either exact, a lower bound or an upper bound
-    const EXACT_BIT: u16 = 1 << 12;
-    const LOWER_BIT: u16 = 1 << 13;
+    const EXACT_BIT: u8 = 1 << 6;
+    const LOWER_BIT: u8 = 1 << 7;
     #[inline(always)]
     pub fn exact_bound(&self) -> bool {
-        let data = u16_from_slice(&self.0[N_REMINDER_BYTES + 3..]);
-        data & TEntry::EXACT_BIT != 0
+        self.0[FLAGS] & TEntry::EXACT_BIT != 0
     }
     #[inline(always)]
     pub fn lower_bound(&self) -> bool {
-        let data = u16_from_slice(&self.0[N_REMINDER_BYTES + 3..]);
-        data & TEntry::LOWER_BIT != 0
+        self.0[FLAGS] & TEntry::LOWER_BIT != 0
     }
 
+    /// move encoding - from, to, promotion & castle flag, see Board::move_from_encoding
     #[inline(always)]
-    pub fn frmto(&self) -> (u8, u8) {
-        let data = u16_from_slice(&self.0[N_REMINDER_BYTES + 3..]);
-        (mgen::ext_frm(data), mgen::ext_to(data))
+    pub fn move_data(&self) -> u16 {
+        u16_from_slice(&self.0[N_REMINDER_BYTES + 3..])
+    }
+
+    #[inline(always)]
+    fn reminder(&self) -> u64 {
+        reminder_from_slice(&self.0)
+    }
+
+    #[inline(always)]
+    fn age(&self) -> u8 {
+        self.0[FLAGS] & AGE_MASK
+    }
+
+    /// replacement priority - the entry with the lowest value is replaced
+    #[inline(always)]
+    fn worth(&self, generation: u8) -> i32 {
+        if self.age() == 0 {
+            i32::MIN // empty
+        } else {
+            // generations run 1..=AGE_MASK and wrap around, skipping 0
+            let stale = (generation + AGE_MASK - self.age()) % AGE_MASK;
+            self.depth() as i32 - 8 * stale as i32
+        }
     }
 
     #[inline(always)]
@@ -134,24 +176,104 @@ impl TEntry {
     }
 }
 
-pub struct Transpositions(Box<[TEntry]>);
+pub struct Transpositions {
+    entries: Box<[TEntry]>, // buckets of bucket_size consecutive entries
+    bucket_size: usize,
+    index_bits: usize,
+    generation: u8,
+}
 
 impl Default for Transpositions {
     fn default() -> Self {
-        // To avoid stack overflow, first create Vec, then convert to Box
-        let vec = vec![TEntry::default(); TABLE_SIZE];
-        Transpositions(vec.into_boxed_slice())
+        Transpositions::new(DEFAULT_MB)
     }
 }
 
-#[inline(always)]
-fn index(key: u64) -> usize {
-    key as usize & MASK
+/// number of index bits for a table of at most mb MB
+fn index_bits(mb: usize, bucket_size: usize) -> usize {
+    let n_buckets = mb.clamp(MIN_MB, MAX_MB) * (1 << 20) / (bucket_size * N_ENTRY_BYTES);
+    (n_buckets.ilog2() as usize).max(MIN_INDEX_BITS)
 }
 
 impl Transpositions {
+    pub fn new(mb: usize) -> Self {
+        Transpositions::with_bucket_size(mb, BUCKET_SIZE)
+    }
+
+    /// a table with bucket_size entries per bucket - 1 => direct mapped, always replace
+    pub fn with_bucket_size(mb: usize, bucket_size: usize) -> Self {
+        assert!((1..=MAX_BUCKET_SIZE).contains(&bucket_size));
+        let index_bits = index_bits(mb, bucket_size);
+        // To avoid stack overflow, first create Vec, then convert to Box
+        let vec = vec![TEntry::default(); bucket_size << index_bits];
+        Transpositions {
+            entries: vec.into_boxed_slice(),
+            bucket_size,
+            index_bits,
+            generation: 1,
+        }
+    }
+
+    /// reallocate for a new size in MB - the table is cleared
+    pub fn resize(&mut self, mb: usize) {
+        if index_bits(mb, self.bucket_size) == self.index_bits {
+            self.clear();
+        } else {
+            // release the old table before allocating the new one
+            self.entries = Box::new([]);
+            *self = Transpositions::with_bucket_size(mb, self.bucket_size);
+        }
+    }
+
+    /// allocated size in MB
+    pub fn size_mb(&self) -> usize {
+        (self.entries.len() * N_ENTRY_BYTES) >> 20
+    }
+
+    /// start a new search - entries from earlier searches become stale
+    pub fn new_search(&mut self) {
+        self.generation = match self.generation {
+            AGE_MASK => 1, // 0 is reserved for empty entries
+            g => g + 1,
+        };
+    }
+
+    /// permille of the table used by the current search - sampled from the first 1000 entries
+    pub fn hashfull(&self) -> usize {
+        let sample = self.entries.iter().take(1000);
+        let n = sample
+            .clone()
+            .filter(|e| e.age() == self.generation)
+            .count();
+        n * 1000 / sample.count()
+    }
+
+    #[inline(always)]
+    fn bucket(&self, key: u64) -> &[TEntry] {
+        let i = (key as usize & ((1 << self.index_bits) - 1)) * self.bucket_size;
+        &self.entries[i..i + self.bucket_size]
+    }
+
+    #[inline(always)]
+    fn bucket_mut(&mut self, key: u64) -> &mut [TEntry] {
+        let i = (key as usize & ((1 << self.index_bits) - 1)) * self.bucket_size;
+        &mut self.entries[i..i + self.bucket_size]
+    }
+
     pub fn store(&mut self, key: u64, depth: u8, score: i16, alpha: i16, beta: i16, m: Move) {
-        let e = &mut self.0[index(key)];
+        let reminder = key >> self.index_bits;
+        let generation = self.generation;
+        let bucket = self.bucket_mut(key);
+        let e = match bucket
+            .iter()
+            .position(|e| e.age() != 0 && e.reminder() == reminder)
+        {
+            Some(j) => &mut bucket[j],
+            None => bucket
+                .iter_mut()
+                .min_by_key(|e| e.worth(generation))
+                .unwrap(),
+        };
         let bound = if score <= alpha {
             0 // Upper bound
         } else if score >= beta {
@@ -159,32 +281,259 @@ impl Transpositions {
         } else {
             TEntry::EXACT_BIT
         };
-        let move_data = (m.data & (mgen::FRM_MASK | mgen::TO_MASK)) | bound;
-        reminder_to_slice(key, &mut e.0); // store reminder
+        let move_data =
+            m.data & (mgen::FRM_MASK | mgen::TO_MASK | mgen::PROMOTE_MASK | mgen::CASTLE);
+        reminder_to_slice(reminder, &mut e.0); // store reminder
         e.0[N_REMINDER_BYTES] = depth;
         i16_to_slice(score, &mut e.0[N_REMINDER_BYTES + 1..]);
         u16_to_slice(move_data, &mut e.0[N_REMINDER_BYTES + 3..]);
+        e.0[FLAGS] = generation | bound;
     }
 
+    /// number of entries
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.entries.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.len() == 0
+        self.entries.is_empty()
     }
 
     pub fn clear(&mut self) {
-        self.0.fill(TEntry::default());
+        self.entries.fill(TEntry::default());
+        self.generation = 1;
+    }
+
+    /// write the table to file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let err = |e| format!("Failed to write {path}: {e}");
+        let mut f = BufWriter::new(File::create(path).map_err(err)?);
+        f.write_all(&header(self.bucket_size, self.index_bits, self.generation))
+            .map_err(err)?;
+        for e in self.entries.iter() {
+            f.write_all(&e.0).map_err(err)?;
+        }
+        f.flush().map_err(err)
+    }
+
+    /// read a table written by save - the table size is taken from the file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let err = |e| format!("Failed to read {path}: {e}");
+        let mut f = BufReader::new(File::open(path).map_err(err)?);
+        let mut h = [0u8; 32];
+        f.read_exact(&mut h).map_err(err)?;
+        let field = |i: usize| u32::from_le_bytes(h[i..i + 4].try_into().unwrap()) as usize;
+        let (bucket_size, index_bits, generation) = (field(20), field(24), field(28) as u8);
+        if h[..8] != MAGIC[..] {
+            return Err(format!("{path}: not a transposition table file"));
+        }
+        if h[8..16] != key_scheme().to_le_bytes() {
+            return Err(format!("{path}: saved with different hash keys"));
+        }
+        if h[..] != header(bucket_size, index_bits, generation)[..] {
+            return Err(format!("{path}: different entry layout"));
+        }
+        if !(1..=MAX_BUCKET_SIZE).contains(&bucket_size)
+            || !(MIN_INDEX_BITS..=self::index_bits(MAX_MB, bucket_size)).contains(&index_bits)
+        {
+            return Err(format!("{path}: bad table size"));
+        }
+        if !(1..=AGE_MASK).contains(&generation) {
+            return Err(format!("{path}: bad generation {generation}"));
+        }
+        // check before allocating - a corrupt header could ask for gigabytes
+        let len = f.get_ref().metadata().map_err(err)?.len();
+        if len != (h.len() + N_ENTRY_BYTES * (bucket_size << index_bits)) as u64 {
+            return Err(format!(
+                "{path}: file size {len} does not match the table size"
+            ));
+        }
+        let mut tt = Transpositions {
+            entries: vec![TEntry::default(); bucket_size << index_bits].into_boxed_slice(),
+            bucket_size,
+            index_bits,
+            generation,
+        };
+        for e in tt.entries.iter_mut() {
+            f.read_exact(&mut e.0).map_err(err)?;
+        }
+        Ok(tt)
     }
 
     pub fn probe(&self, key: u64) -> Option<&TEntry> {
-        let entry = &self.0[index(key)];
-        let reminder = key >> N_INDEX_BITS;
-        if reminder == reminder_from_slice(&entry.0) {
-            Some(entry)
-        } else {
-            None
+        let reminder = key >> self.index_bits;
+        self.bucket(key)
+            .iter()
+            .find(|e| e.age() != 0 && e.reminder() == reminder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(tt: &mut Transpositions, key: u64, depth: u8) {
+        tt.store(key, depth, 42, 0, 100, Move { data: 0, val: 0 });
+    }
+
+    /// per process temp file - removed when dropped, also when a test fails
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let name = format!("{}_{name}", std::process::id());
+            TempFile(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn test_size() {
+        let tt = Transpositions::new(DEFAULT_MB);
+        assert_eq!((tt.len(), tt.size_mb()), (1 << 24, 208));
+        let mut tt = Transpositions::new(1);
+        assert_eq!(tt.len(), BUCKET_SIZE << MIN_INDEX_BITS);
+        tt.resize(64);
+        assert_eq!(tt.len(), BUCKET_SIZE << 20);
+        assert!(tt.size_mb() <= 64);
+    }
+
+    #[test]
+    fn test_probe() {
+        let mut tt = Transpositions::new(1);
+        let key = 0xdead_beef_0123_4567;
+        assert!(tt.probe(0).is_none()); // empty entries never match
+        store(&mut tt, key, 3);
+        let e = tt.probe(key).unwrap();
+        assert_eq!((e.depth(), e.score(), e.exact_bound()), (3, 42, true));
+        assert!(tt.probe(key ^ (1 << 40)).is_none()); // same index, other reminder
+    }
+
+    #[test]
+    fn test_promotion() {
+        let mut tt = Transpositions::new(1);
+        let data = mgen::PROMOTE_KNIGHT | 0b111111_000000;
+        tt.store(1, 1, -10, 0, 100, Move { data, val: 0 });
+        let e = tt.probe(1).unwrap();
+        assert_eq!(e.move_data(), data);
+        let m = Move {
+            data: e.move_data(),
+            val: 0,
+        };
+        assert_eq!((m.frm_sq().name(), m.to_sq().name()), ("h1", "a8"));
+        assert!(!e.exact_bound() && !e.lower_bound()); // upper bound
+    }
+
+    #[test]
+    fn test_castle() -> Result<(), String> {
+        // Chess960 king on f1, rook on h1 - castling and the king step are both f1g1
+        for fen in [
+            "4k3/8/8/8/8/8/8/4K2R w K - 0 1",
+            "5k2/8/8/8/8/8/8/5K1R w H - 0 1",
+        ] {
+            let board = mgen::Board::from_fen(fen)?;
+            for m in board.legal_moves() {
+                let mut tt = Transpositions::new(1);
+                tt.store(board.hash, 3, 0, -100, 100, m);
+                let data = tt.probe(board.hash).unwrap().move_data();
+                let decoded = board.move_from_encoding(data).map(|m| m.data);
+                assert_eq!(decoded, Some(m.data), "{fen} {m}");
+            }
+            assert!(board.legal_moves().iter().any(|m| board.is_castle(m)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut tt = Transpositions::new(1);
+        let key = 0xdead_beef_0123_4567;
+        store(&mut tt, key, 3);
+        tt.new_search();
+        let file = TempFile::new("mateus_test.tt");
+        let path = file.0.to_str().unwrap();
+        tt.save(path).unwrap();
+        let tt2 = Transpositions::load(path).unwrap();
+        assert_eq!((tt2.len(), tt2.generation), (tt.len(), tt.generation));
+        assert_eq!(tt2.probe(key).map(|e| e.depth()), Some(3));
+
+        // reject a different entry layout
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes[16] += 1;
+        std::fs::write(path, &bytes).unwrap();
+        assert!(Transpositions::load(path).is_err());
+
+        // reject a generation outside 1..=AGE_MASK - it would corrupt the bound bits
+        bytes[16] -= 1;
+        for generation in [0, AGE_MASK + 1, 255] {
+            bytes[28] = generation;
+            std::fs::write(path, &bytes).unwrap();
+            assert!(Transpositions::load(path).is_err());
+        }
+
+        // reject a truncated file
+        bytes[28] = tt.generation;
+        std::fs::write(path, &bytes[..bytes.len() - 1]).unwrap();
+        let err = Transpositions::load(path).err().unwrap();
+        assert!(err.contains("does not match the table size"), "{err}");
+    }
+
+    #[test]
+    fn test_replacement() {
+        let mut tt = Transpositions::new(1);
+        let key = |i: u64| (i << 32) | 7; // same bucket
+        for i in 0..BUCKET_SIZE as u64 {
+            store(&mut tt, key(i), 10 + i as u8);
+        }
+        // full bucket - the shallowest entry is replaced
+        store(&mut tt, key(9), 1);
+        assert!(tt.probe(key(0)).is_none() && tt.probe(key(1)).is_some());
+
+        // next search - stale entries are replaced before deep ones from this search
+        tt.new_search();
+        store(&mut tt, key(10), 5);
+        store(&mut tt, key(11), 1);
+        assert!(tt.probe(key(10)).is_some() && tt.probe(key(11)).is_some());
+        assert!(tt.probe(key(9)).is_none()); // least valuable stale entry
+        assert!(tt.probe(key(3)).is_some());
+    }
+
+    #[test]
+    fn test_age_wrap() {
+        let mut tt = Transpositions::new(1);
+        tt.generation = AGE_MASK;
+        store(&mut tt, 7, 5);
+        let e = *tt.probe(7).unwrap();
+        assert_eq!(e.worth(AGE_MASK), 5);
+        tt.new_search(); // wraps to 1 - the entry is one search old
+        assert_eq!(tt.generation, 1);
+        assert_eq!(e.worth(tt.generation), 5 - 8);
+    }
+
+    #[test]
+    fn test_direct_mapped() {
+        let mut tt = Transpositions::with_bucket_size(1, 1);
+        assert_eq!(tt.len(), 1 << index_bits(1, 1));
+        let key = |i: u64| (i << 32) | 7; // same bucket
+        store(&mut tt, key(0), 10);
+        store(&mut tt, key(1), 1); // always replace
+        assert!(tt.probe(key(0)).is_none() && tt.probe(key(1)).is_some());
+    }
+
+    #[test]
+    fn test_hashfull() {
+        let mut tt = Transpositions::new(1);
+        assert_eq!(tt.hashfull(), 0);
+        for i in 0..250 {
+            store(&mut tt, i, 1); // one entry in each of the first 250 buckets
         }
+        assert_eq!(tt.hashfull(), 250);
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
     }
 }